@@ -0,0 +1,70 @@
+use structopt::StructOpt;
+use transit_topo::clients::{AuthOptions, RetryOptions};
+use transit_topo::store::WikibaseStore;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "cleanup-data-sources")]
+struct Opt {
+    /// Identifier of the topo id property
+    #[structopt(short, long, default_value = "P1")]
+    topo_id_id: String,
+
+    /// Only cleanup the data sources of this producer
+    #[structopt(short, long)]
+    producer: Option<String>,
+
+    /// Endpoint of the wikibase api
+    #[structopt(short, long, default_value = "http://localhost:8181/api.php")]
+    api: String,
+
+    /// Endpoint of the sparql query serive
+    #[structopt(short, long, default_value = "http://localhost:8989/bigdata/sparql")]
+    sparql: String,
+
+    /// Delay in hours after which an unused data source is deleted even if its import has not failed.
+    /// Before it, the import might still be running or be resumed
+    #[structopt(long, default_value = "24")]
+    grace_period_hours: i64,
+
+    /// Only print the data sources that would be deleted
+    #[structopt(long)]
+    dry_run: bool,
+
+    #[structopt(flatten)]
+    auth: AuthOptions,
+
+    #[structopt(flatten)]
+    retry: RetryOptions,
+}
+
+fn main() {
+    transit_topo::log::init();
+
+    let opt = Opt::from_args();
+    let retry_policy = opt.retry.policy().expect("invalid retry options");
+    let mut store = if opt.dry_run {
+        WikibaseStore::read_only(&opt.sparql, &opt.topo_id_id).unwrap()
+    } else {
+        let credentials = opt.auth.credentials().expect("invalid authentication");
+        WikibaseStore::new(&opt.api, &opt.sparql, &opt.topo_id_id, credentials).unwrap()
+    };
+    store.set_retry_policy(retry_policy);
+
+    let data_sources = store
+        .query
+        .find_unreferenced_data_sources(
+            opt.producer.as_deref(),
+            chrono::Duration::hours(opt.grace_period_hours),
+        )
+        .expect("impossible to search for the data sources");
+    log::info!("{} data sources are not used", data_sources.len());
+
+    for data_source in data_sources {
+        println!("{}", data_source);
+        if let Some(api) = &store.api {
+            api.delete_entity(&data_source, "no entity is linked to this data source")
+                .expect("impossible to delete the data source");
+            log::info!("data source {} deleted", data_source);
+        }
+    }
+}
@@ -1,10 +1,14 @@
-use itertools::Itertools;
 use regex::Regex;
 use structopt::StructOpt;
 use transit_topo::{
-    clients::{api_client, sparql_client::read_id_from_url},
+    clients::{
+        api_client,
+        sparql_builder::{lang_literal, prefixed, var, wdt, Term, WhereClause},
+        sparql_client::read_id_from_url,
+        AuthOptions, RetryOptions,
+    },
+    store::{Store, WikibaseStore},
     topo_query::TopoQuery,
-    GtfsImporter,
 };
 
 use clap::arg_enum;
@@ -38,8 +42,6 @@ impl EntityType {
 
 lazy_static::lazy_static! {
     static ref CLAIM_REGEX: Regex = Regex::new(r"^(.*)=(.*)$").unwrap();
-
-    static ref CLAIM_ITEM_REGEX: Regex = Regex::new(r"^wd:(.*)$").unwrap();
 }
 
 #[derive(StructOpt, Debug)]
@@ -103,45 +105,13 @@ enum Opt {
         /// --claim "@instance_of=@producer"
         #[structopt(short, long = "claim")]
         claims: Vec<String>,
-    },
-}
-
-// convert the Json representation of a simple struct (either client::Properties or client::Items)
-// into a hashmap field => value
-// it has lots of `expect`, because it should never fail, as it depends on the code (so checked at build time)
-fn as_map(val: serde_json::Value) -> std::collections::HashMap<String, String> {
-    val.as_object()
-        .expect("invalid value")
-        .iter()
-        .map(|(k, v)| (k.clone(), v.as_str().expect("value not string").to_owned()))
-        .collect()
-}
 
-// replace in the claims known properties or known items
-// the known fields are taken as "@field"
-// for example to add a claims saying that the entity should be a `instance of` `producer`:
-// --claims "@instance_of=@producer"
-fn replace_known_entities(
-    claims: Vec<(String, String)>,
-    entities: &transit_topo::known_entities::EntitiesId,
-) -> Vec<(String, String)> {
-    let prop =
-        as_map(serde_json::to_value(&entities.properties).expect("impossible to serialize prop"));
-    let items =
-        as_map(serde_json::to_value(&entities.items).expect("impossible to serialize items"));
+        #[structopt(flatten)]
+        auth: AuthOptions,
 
-    claims
-        .into_iter()
-        .map(|(mut claim_prop, mut claim_value)| {
-            for (k, v) in prop.iter() {
-                claim_prop = claim_prop.replace(&format!("@{}", k), v);
-            }
-            for (k, v) in items.iter() {
-                claim_value = claim_value.replace(&format!("@{}", k), &format!("wd:{}", v));
-            }
-            (claim_prop, claim_value)
-        })
-        .collect()
+        #[structopt(flatten)]
+        retry: RetryOptions,
+    },
 }
 
 fn parse_claims(
@@ -158,7 +128,26 @@ fn parse_claims(
         })
         .collect();
 
-    Ok(replace_known_entities(claims?, entities))
+    Ok(entities.replace_known_entities(claims?))
+}
+
+// add the claims to the where clause, as triples on the `?item` variable
+fn add_claims(
+    where_clause: WhereClause,
+    claims: &[(String, String)],
+) -> Result<WhereClause, anyhow::Error> {
+    claims
+        .iter()
+        .try_fold(where_clause, |where_clause, (p, v)| {
+            let property = if p.contains(':') {
+                // if the property contains a ':', we consider that we do not need to namespace it
+                // it makes it possible to look for exemple by label: rdfs:label
+                Term::parse(p)?
+            } else {
+                wdt(p)
+            };
+            Ok(where_clause.triple(var("item"), property, Term::parse(v)?))
+        })
 }
 
 fn search(topo_id_id: &str, sparql: &str, claims: &[String]) -> Result<Vec<String>, anyhow::Error> {
@@ -169,21 +158,7 @@ fn search(topo_id_id: &str, sparql: &str, claims: &[String]) -> Result<Vec<Strin
 
     let claims = parse_claims(claims, &query.known_entities)?;
 
-    let where_clause = format!(
-        "?item {claims}.",
-        claims = claims
-            .iter()
-            .map(|(p, v)| {
-                if p.contains(':') {
-                    // if the property contains a ':', we consider that we do not need to namespace it
-                    // it makes it possible to look for exemple by label: rdfs:label
-                    format!("{} {}", p, v)
-                } else {
-                    format!("wdt:{} {}", p, v)
-                }
-            })
-            .join("; ")
-    );
+    let where_clause = add_claims(WhereClause::new(), &claims)?;
 
     let res = query.client.sparql(&["?item"], &where_clause)?;
 
@@ -195,28 +170,24 @@ fn search(topo_id_id: &str, sparql: &str, claims: &[String]) -> Result<Vec<Strin
 }
 
 fn create_entity(
+    store: &WikibaseStore,
     entity_type: EntityType,
     label: &str,
-    topo_id_id: &str,
-    api: &str,
-    sparql: &str,
     unique_claims: &[String],
     claims: &[String],
 ) -> Result<String, anyhow::Error> {
-    let importer = GtfsImporter::new(api, sparql, topo_id_id)?;
-
-    let parsed_unique_claims = parse_claims(unique_claims, &importer.query.known_entities)?;
-
-    let where_clause = format!(
-        r#"?item rdfs:label "{}"@en; {}."#,
-        label,
-        parsed_unique_claims
-            .iter()
-            .map(|(p, v)| format!("wdt:{} {}", p, v))
-            .join("; ")
-    );
+    let parsed_unique_claims = parse_claims(unique_claims, &store.query.known_entities)?;
+
+    let where_clause = add_claims(
+        WhereClause::new().triple(
+            var("item"),
+            prefixed("rdfs", "label"),
+            lang_literal(label, "en"),
+        ),
+        &parsed_unique_claims,
+    )?;
     // We check that there is not yet an entity with this label
-    match importer
+    match store
         .query
         .client
         .sparql(&["?item"], &where_clause)?
@@ -236,7 +207,7 @@ fn create_entity(
         }
         [] => {
             log::info!("no entity \"{}\" exists, creating one", label);
-            let claims: Vec<_> = parse_claims(claims, &importer.query.known_entities)?
+            let claims: Vec<_> = parse_claims(claims, &store.query.known_entities)?
                 .iter()
                 .chain(parsed_unique_claims.iter())
                 .map(|(prop, value)| {
@@ -244,23 +215,23 @@ fn create_entity(
                     // for the rest api does not want those namespace
                     // so we use those namespace to know if the claim is on a item or a string
                     let prop = prop.replace("wdt:", "");
-                    // same, the api does not want '<>' around the urls (but the sparql does)
-                    let value = value.replace("<", "");
-                    let value = value.replace(">", "");
-
-                    match CLAIM_ITEM_REGEX.captures(&value) {
-                        None => api_client::claim_string(&prop, &value),
-                        Some(c) => api_client::claim_item(&prop, &c[1]),
-                    }
+                    // same, the api does not want '<>' around the urls nor quotes around the strings
+                    // (but the sparql does)
+                    Ok(match Term::parse(value)? {
+                        Term::Prefixed {
+                            ref prefix,
+                            ref local,
+                        } if prefix == "wd" => api_client::claim_item(&prop, local),
+                        Term::Iri(value) | Term::Literal { value, .. } => {
+                            api_client::claim_string(&prop, &value)
+                        }
+                        _ => api_client::claim_string(&prop, value),
+                    })
                 })
-                .collect();
+                .collect::<Result<_, anyhow::Error>>()?;
 
             log::debug!("creating entity \"{}\" with claims {:?}", label, &claims);
-            let id = importer.writer.client.create_object(
-                entity_type.get_object_type(),
-                label,
-                claims,
-            )?;
+            let id = store.create_entity(entity_type.get_object_type(), label, claims)?;
             log::info!("created entity \"{}\" with id {}", label, id);
             Ok(id.to_owned())
         }
@@ -303,17 +274,16 @@ fn main() {
             label,
             unique_claims,
             claims,
+            auth,
+            retry,
         } => {
-            let id = create_entity(
-                entity_type,
-                &label,
-                &topo_id_id,
-                &api,
-                &sparql,
-                &unique_claims,
-                &claims,
-            )
-            .expect("impossible to create entity");
+            let retry_policy = retry.policy().expect("invalid retry options");
+            let credentials = auth.credentials().expect("invalid authentication");
+            let mut store = WikibaseStore::new(&api, &sparql, &topo_id_id, credentials)
+                .expect("impossible to connect to the wikibase");
+            store.set_retry_policy(retry_policy);
+            let id = create_entity(&store, entity_type, &label, &unique_claims, &claims)
+                .expect("impossible to create entity");
             println!("{}", id);
         }
     }
@@ -1,4 +1,9 @@
+use std::sync::Arc;
 use structopt::StructOpt;
+use transit_topo::clients::{AuthOptions, RetryOptions};
+use transit_topo::importer::{ImportError, ImportOptions};
+use transit_topo::journal::Journal;
+use transit_topo::store::{QuickStatementsStore, Store, WikibaseStore};
 use transit_topo::GtfsImporter;
 
 #[derive(StructOpt, Debug)]
@@ -32,28 +37,182 @@ struct Opt {
     /// and you want to force the update of the already inserted items
     #[structopt(long)]
     override_existing: bool,
+
+    /// Mark the routes and stops of the producer that are not in the GTFS anymore
+    /// with a `no longer present since` claim
+    #[structopt(long)]
+    deprecate_missing: bool,
+
+    /// Import the GTFS even if the same file (same sha256)
+    /// has already been imported for this producer
+    #[structopt(long)]
+    force: bool,
+
+    /// Only read the database and print the import plan as json,
+    /// without writing anything
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Do not edit the database, write all the edits in this file as QuickStatements (V1) commands instead.
+    /// The statements referencing the created items are written in `<file>.pending`, with the placeholders
+    /// `NEW1`, `NEW2`... in the order of the `CREATE`, to resolve with `resolve-quick-statements`
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &["dry-run", "journal", "checkpoint", "wait-for-query-service"]
+    )]
+    quick_statements: Option<std::path::PathBuf>,
+
+    /// When an entity cannot be imported, continue with the others instead of stopping.
+    /// The failed entities are listed at the end and the exit code is not 0
+    #[structopt(long)]
+    keep_going: bool,
+
+    /// Write the report of the import (counts by action, warnings, errors and
+    /// duration of each phase) as json in this file
+    #[structopt(long, parse(from_os_str))]
+    report: Option<std::path::PathBuf>,
+
+    /// Journal file of the created entities.
+    /// It is read before querying the sparql endpoint, so an import run
+    /// shortly after another one does not create duplicates
+    #[structopt(long, parse(from_os_str))]
+    journal: Option<std::path::PathBuf>,
+
+    /// Number of entities imported at the same time.
+    /// The results of the import do not depend on it
+    #[structopt(long, default_value = "4")]
+    concurrent_edits: usize,
+
+    /// Checkpoint file, to resume the import where it stopped if it is interrupted.
+    /// It is removed at the end of the import
+    #[structopt(long, parse(from_os_str))]
+    checkpoint: Option<std::path::PathBuf>,
+
+    /// At the end of the import, wait until the query service has loaded all the edits
+    #[structopt(long)]
+    wait_for_query_service: bool,
+
+    /// Maximum time to wait for the query service, in seconds
+    #[structopt(long, default_value = "600")]
+    wait_timeout: u64,
+
+    #[structopt(flatten)]
+    auth: AuthOptions,
+
+    #[structopt(flatten)]
+    retry: RetryOptions,
 }
 
 fn main() {
     transit_topo::log::init();
 
     let opt = Opt::from_args();
-    let importer = GtfsImporter::new(&opt.api, &opt.sparql, &opt.topo_id_id).unwrap();
+    let retry_policy = opt.retry.policy().expect("invalid retry options");
+    let mut wikibase = if opt.dry_run || opt.quick_statements.is_some() {
+        WikibaseStore::read_only(&opt.sparql, &opt.topo_id_id).unwrap()
+    } else {
+        let credentials = opt.auth.credentials().expect("invalid authentication");
+        WikibaseStore::new(&opt.api, &opt.sparql, &opt.topo_id_id, credentials).unwrap()
+    };
+    wikibase.set_retry_policy(retry_policy);
+    let wikibase = Arc::new(wikibase);
+    let quick_statements = opt.quick_statements.as_ref().map(|path| {
+        let mut pending_path = path.clone().into_os_string();
+        pending_path.push(".pending");
+        let file = std::fs::File::create(path).expect("impossible to create the output file");
+        let pending =
+            std::fs::File::create(pending_path).expect("impossible to create the pending file");
+        Arc::new(QuickStatementsStore::new(
+            wikibase.clone(),
+            Box::new(file),
+            Box::new(pending),
+        ))
+    });
+    let store: Arc<dyn Store> = match &quick_statements {
+        Some(quick_statements) => quick_statements.clone(),
+        None => wikibase.clone(),
+    };
+    let mut importer = if opt.dry_run {
+        GtfsImporter::new_dry_run(store.clone()).unwrap()
+    } else {
+        GtfsImporter::new(store.clone()).unwrap()
+    };
+    // the placeholders of the QuickStatements are numbered in the order of the creations,
+    // the entities are created one by one so that the file is the same for each export
+    let concurrent_edits = if opt.quick_statements.is_some() {
+        1
+    } else {
+        opt.concurrent_edits
+    };
+    importer
+        .set_concurrent_edits(concurrent_edits)
+        .expect("invalid number of concurrent edits");
+    importer.keep_going = opt.keep_going;
+    if let Some(journal) = &opt.journal {
+        importer.journal = Some(Journal::open(journal).expect("impossible to open the journal"));
+    }
+
+    if opt.checkpoint.is_some() {
+        transit_topo::checkpoint::handle_interruptions().unwrap();
+    }
 
     log::info!("Searching the producer by id");
-    let producer_label = importer
-        .query
-        .get_producer_label(&opt.producer)
+    let producer_label = store
+        .find_producer(&opt.producer)
         .expect("unable to search for producer")
         .unwrap_or_else(|| panic!("no producer with id {}", &opt.producer));
     log::info!("Found the producer “{}”", &producer_label);
     log::info!("Starting the importation of lines");
-    importer
-        .import_gtfs(
-            &opt.gtfs_filename,
-            &opt.producer,
-            &producer_label,
-            opt.override_existing,
-        )
-        .expect("unable to import");
+    // the report of a failed import is written too, to know what has been done
+    let (report, error) = match importer.import_gtfs(
+        &opt.gtfs_filename,
+        &opt.producer,
+        &producer_label,
+        &ImportOptions {
+            override_existing: opt.override_existing,
+            deprecate_missing: opt.deprecate_missing,
+            force: opt.force,
+            checkpoint: opt.checkpoint.clone(),
+        },
+    ) {
+        Ok(report) => (report, None),
+        Err(ImportError { error, report }) => (report, Some(error)),
+    };
+
+    // the created items are written even if the import failed, like the report
+    if let Some(quick_statements) = &quick_statements {
+        quick_statements
+            .finish()
+            .expect("impossible to write the QuickStatements");
+    }
+
+    if error.is_none() && opt.wait_for_query_service {
+        wikibase
+            .wait_for_query_service(std::time::Duration::from_secs(opt.wait_timeout))
+            .expect("the query service is not up to date");
+    }
+
+    log::info!("{}", report.summary());
+    if let Some(path) = &opt.report {
+        let file = std::fs::File::create(path).expect("impossible to create the report file");
+        serde_json::to_writer_pretty(file, &report).expect("impossible to write the report");
+    }
+    if opt.dry_run {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("impossible to serialize the plan")
+        );
+    }
+    if let Some(error) = error {
+        log::error!("unable to import: {:?}", error);
+        std::process::exit(1);
+    }
+    if !report.failures.is_empty() {
+        log::error!(
+            "the import is incomplete, {} entities could not be imported",
+            report.failures.len()
+        );
+        std::process::exit(1);
+    }
 }
@@ -1,4 +1,5 @@
 use structopt::StructOpt;
+use transit_topo::clients::AuthOptions;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "basic")]
@@ -9,12 +10,16 @@ struct Opt {
     /// Endpoint of the sparql query serive
     #[structopt(short, long, default_value = "http://localhost:8989/bigdata/sparql")]
     sparql: String,
+
+    #[structopt(flatten)]
+    auth: AuthOptions,
 }
 
 fn main() {
     transit_topo::log::init();
 
     let opt = Opt::from_args();
-    transit_topo::database_initializer::initial_populate(&opt.api, &opt.sparql)
+    let credentials = opt.auth.credentials().expect("invalid authentication");
+    transit_topo::database_initializer::initial_populate(&opt.api, &opt.sparql, credentials)
         .expect("impossible to populate wikibase");
 }
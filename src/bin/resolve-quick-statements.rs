@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+use transit_topo::store::resolve_placeholders;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "resolve-quick-statements")]
+struct Opt {
+    /// File with the ids of the items created by the QuickStatements, one by line,
+    /// in the order of their `CREATE`
+    #[structopt(long, parse(from_os_str))]
+    ids: PathBuf,
+
+    /// Pending QuickStatements written by `import-gtfs`, with the `NEW1`, `NEW2`... placeholders
+    #[structopt(parse(from_os_str))]
+    pending: PathBuf,
+}
+
+fn main() {
+    transit_topo::log::init();
+
+    let opt = Opt::from_args();
+    let ids = std::fs::read_to_string(&opt.ids).expect("impossible to read the ids");
+    let ids = ids
+        .lines()
+        .map(|id| id.trim().to_owned())
+        .filter(|id| !id.is_empty())
+        .collect::<Vec<_>>();
+    let pending = std::fs::read_to_string(&opt.pending)
+        .expect("impossible to read the pending QuickStatements");
+    let resolved =
+        resolve_placeholders(&pending, &ids).expect("impossible to resolve the placeholders");
+    print!("{}", resolved);
+}
@@ -0,0 +1,264 @@
+//! Checkpoint of an import, to resume it where it stopped
+//!
+//! The checkpoint file is a log of the import, with one json entry by line:
+//! the data source, the wikibase ids of the entities created and the finished phases.
+//! Like the journal, each entry is appended to the file as soon as it happens,
+//! so nothing is lost if the program crashes.
+use crate::import_report::EntityKind;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Error of an import stopped by the user
+#[derive(thiserror::Error, Debug)]
+#[error("import interrupted, it can be resumed with the checkpoint {0:?}")]
+pub struct Interrupted(PathBuf);
+
+/// Phases of an import, in their execution order
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Routes,
+    Stops,
+    RoutePatterns,
+    Relations,
+}
+
+/// A line of the checkpoint file
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    /// first line of the file, to check that the checkpoint is for the same import
+    Start {
+        producer: String,
+        /// sha256 of the imported file
+        sha256: String,
+    },
+    DataSource(String),
+    Created {
+        kind: EntityKind,
+        gtfs_id: String,
+        id: String,
+    },
+    Finished(Phase),
+    /// the relation claims of the entity have been added
+    Edited(String),
+}
+
+#[derive(Debug, Default)]
+struct State {
+    data_source: Option<String>,
+    /// wikibase id of the entities created by the import, by kind and gtfs id
+    created: HashMap<(EntityKind, String), String>,
+    finished_phases: BTreeSet<Phase>,
+    /// entities whose relation claims have already been added
+    edited_entities: BTreeSet<String>,
+}
+
+impl State {
+    fn apply(&mut self, entry: Entry) {
+        match entry {
+            Entry::Start { .. } => {}
+            Entry::DataSource(id) => self.data_source = Some(id),
+            Entry::Created { kind, gtfs_id, id } => {
+                self.created.insert((kind, gtfs_id), id);
+            }
+            Entry::Finished(phase) => {
+                self.finished_phases.insert(phase);
+                self.edited_entities.clear();
+            }
+            Entry::Edited(id) => {
+                self.edited_entities.insert(id);
+            }
+        }
+    }
+}
+
+pub struct Checkpoint {
+    path: PathBuf,
+    file: Mutex<std::fs::File>,
+    state: Mutex<State>,
+}
+
+/// Catch Ctrl-C to stop the import cleanly, after the current edit.
+/// A second Ctrl-C stops the program immediately
+pub fn handle_interruptions() -> Result<(), anyhow::Error> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        log::warn!("interruption requested, stopping after the current edit");
+    })
+    .context("impossible to catch the interruptions")
+}
+
+/// Read the content of an existing checkpoint.
+/// The last line is incomplete if the program crashed while writing it, it is removed from the file
+fn read_content(path: &Path) -> Result<String, anyhow::Error> {
+    let mut content = std::fs::read_to_string(path)
+        .with_context(|| format!("impossible to read checkpoint {:?}", path))?;
+    if !content.is_empty() && !content.ends_with('\n') {
+        log::warn!("removing the incomplete last line of checkpoint {:?}", path);
+        content.truncate(content.rfind('\n').map_or(0, |i| i + 1));
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|f| f.set_len(content.len() as u64))
+            .with_context(|| format!("impossible to write checkpoint {:?}", path))?;
+    }
+    Ok(content)
+}
+
+impl Checkpoint {
+    /// Open the checkpoint of the import of the file `sha256` for the producer.
+    /// If the file already exists it must be the checkpoint of the same import
+    pub fn open(path: &Path, producer: &str, sha256: &str) -> Result<Self, anyhow::Error> {
+        let mut state = State::default();
+        let content = if path.exists() {
+            read_content(path)?
+        } else {
+            String::new()
+        };
+        let mut entries = content
+            .lines()
+            .map(|line| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("invalid line in checkpoint {:?}: {}", path, line))
+            })
+            .collect::<Result<Vec<Entry>, _>>()?
+            .into_iter();
+        let start = match entries.next() {
+            Some(Entry::Start {
+                producer: p,
+                sha256: s,
+            }) => {
+                if p != producer || s != sha256 {
+                    return Err(anyhow::anyhow!(
+                        "the checkpoint {:?} is for another import (producer {}, sha256 {}), remove it to start a new import",
+                        path,
+                        p,
+                        s
+                    ));
+                }
+                entries.for_each(|e| state.apply(e));
+                log::info!(
+                    "resuming the import from checkpoint {:?}, finished phases: {:?}",
+                    path,
+                    state.finished_phases
+                );
+                None
+            }
+            Some(e) => {
+                return Err(anyhow::anyhow!(
+                    "invalid checkpoint {:?}, it starts with {:?}",
+                    path,
+                    e
+                ))
+            }
+            None => Some(Entry::Start {
+                producer: producer.to_owned(),
+                sha256: sha256.to_owned(),
+            }),
+        };
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("impossible to open checkpoint {:?}", path))?;
+        let checkpoint = Self {
+            path: path.to_owned(),
+            file: Mutex::new(file),
+            state: Mutex::new(state),
+        };
+        if let Some(start) = start {
+            checkpoint.append(start)?;
+        }
+        Ok(checkpoint)
+    }
+
+    /// Write an entry at the end of the file and apply it to the state
+    fn append(&self, entry: Entry) -> Result<(), anyhow::Error> {
+        {
+            let mut file = self.file.lock().unwrap();
+            writeln!(file, "{}", serde_json::to_string(&entry)?)
+                .and_then(|_| file.flush())
+                .with_context(|| format!("impossible to write checkpoint {:?}", self.path))?;
+        }
+        self.state.lock().unwrap().apply(entry);
+        Ok(())
+    }
+
+    /// The import is finished, the checkpoint is not needed anymore
+    pub fn remove(&self) -> Result<(), anyhow::Error> {
+        std::fs::remove_file(&self.path)
+            .with_context(|| format!("impossible to remove checkpoint {:?}", self.path))
+    }
+
+    /// Fails if the user asked to stop the import
+    pub fn check_interrupted(&self) -> Result<(), anyhow::Error> {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            return Err(Interrupted(self.path.clone()).into());
+        }
+        Ok(())
+    }
+
+    pub fn data_source(&self) -> Option<String> {
+        self.state.lock().unwrap().data_source.clone()
+    }
+
+    pub fn set_data_source(&self, id: &str) -> Result<(), anyhow::Error> {
+        self.append(Entry::DataSource(id.to_owned()))
+    }
+
+    /// wikibase id of the entity created before the interruption of the import
+    pub fn created(&self, kind: EntityKind, gtfs_id: &str) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .created
+            .get(&(kind, gtfs_id.to_owned()))
+            .cloned()
+    }
+
+    pub fn add_created(
+        &self,
+        kind: EntityKind,
+        gtfs_id: &str,
+        id: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.append(Entry::Created {
+            kind,
+            gtfs_id: gtfs_id.to_owned(),
+            id: id.to_owned(),
+        })
+    }
+
+    pub fn is_finished(&self, phase: Phase) -> bool {
+        self.state.lock().unwrap().finished_phases.contains(&phase)
+    }
+
+    pub fn finish(&self, phase: Phase) -> Result<(), anyhow::Error> {
+        self.append(Entry::Finished(phase))?;
+        log::info!("phase {:?} finished", phase);
+        Ok(())
+    }
+
+    /// The relation claims have already been added to the entity
+    pub fn is_edited(&self, entity_id: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .edited_entities
+            .contains(entity_id)
+    }
+
+    pub fn add_edited(&self, entity_id: &str) -> Result<(), anyhow::Error> {
+        self.append(Entry::Edited(entity_id.to_owned()))
+    }
+}
@@ -1,13 +1,22 @@
 use crate::clients::api_structures::*;
-use crate::clients::ApiError;
+use crate::clients::retry::{self, Failure, RetryPolicy};
+use crate::clients::{ApiError, Credentials};
 use crate::entity;
 use anyhow::anyhow;
 use regex::Regex;
 use serde_json::json;
 use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
 
 const WIKIBASE_LABEL_CONFLICT: &str = "wikibase-validator-label-conflict";
 
+/// Api error codes meaning that the session has expired, or that the csrf token is not valid anymore
+const SESSION_ERRORS: &[&str] = &["assertuserfailed", "badtoken", "notloggedin"];
+
+/// Api error codes of the edits that can succeed if they are sent again later
+const TRANSIENT_ERRORS: &[&str] = &["maxlag", "editconflict", "ratelimited", "readonly"];
+
 lazy_static::lazy_static! {
     // the message in the api response is in the form "[[Property:P1|P1]]"
     // and in this example we want to extract "P1"
@@ -19,6 +28,7 @@ pub enum PropertyDataType {
     Url,
     Item,
     Coord,
+    Quantity,
 }
 
 impl std::string::ToString for PropertyDataType {
@@ -28,6 +38,7 @@ impl std::string::ToString for PropertyDataType {
             Self::Item => "wikibase-item".to_owned(),
             Self::Url => "url".to_owned(),
             Self::Coord => "globe-coordinate".to_owned(),
+            Self::Quantity => "quantity".to_owned(),
         }
     }
 }
@@ -47,36 +58,202 @@ impl std::string::ToString for ObjectType {
 }
 
 pub struct ApiClient {
+    /// the client keeps the session cookies
     client: reqwest::Client,
     endpoint: String,
-    token: String,
+    /// csrf token, renewed at each login
+    token: Mutex<String>,
+    /// without credentials the edits are anonymous
+    credentials: Option<Credentials>,
+    retry: RetryPolicy,
+    /// the entity and the id of the most recent revision made by the client
+    last_revision: Mutex<Option<(String, u64)>>,
 }
 
 impl ApiClient {
+    /// create an anonymous client
     pub fn new(endpoint: &str) -> Result<Self, ApiError> {
-        let client = reqwest::Client::new();
-        let res = client
-            .get(endpoint)
-            .query(&[("format", "json"), ("action", "query"), ("meta", "tokens")])
-            .send()?
-            .json::<TokenResponse>()?;
-        Ok(ApiClient {
+        Self::with_credentials(endpoint, None)
+    }
+
+    /// create a client, logged in with the given bot credentials
+    pub fn with_credentials(
+        endpoint: &str,
+        credentials: Option<Credentials>,
+    ) -> Result<Self, ApiError> {
+        let client = reqwest::Client::builder().cookie_store(true).build()?;
+        let api_client = ApiClient {
             client,
             endpoint: endpoint.to_owned(),
-            token: res.query.tokens.csrftoken,
+            token: Mutex::new(String::new()),
+            credentials,
+            retry: RetryPolicy::default(),
+            last_revision: Mutex::new(None),
+        };
+        api_client.login()?;
+        Ok(api_client)
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// the entity and the id of the most recent revision made by the client
+    pub fn last_revision(&self) -> Option<(String, u64)> {
+        self.last_revision.lock().unwrap().clone()
+    }
+
+    fn track_revision(&self, entity: &InsertEntity) {
+        if let Some(revision) = entity.lastrevid {
+            let mut last_revision = self.last_revision.lock().unwrap();
+            if last_revision.as_ref().map_or(true, |(_, r)| *r < revision) {
+                *last_revision = Some((entity.id.clone(), revision));
+            }
+        }
+    }
+
+    /// send a GET request to the api, the transient errors are retried
+    fn get(&self, params: &[(&str, &str)]) -> Result<reqwest::Response, ApiError> {
+        let action = params
+            .iter()
+            .find(|(k, _)| *k == "action")
+            .map_or("api query", |(_, v)| *v);
+        Ok(retry::send(&self.retry, action, || {
+            self.client
+                .get(&self.endpoint)
+                .query(&[("format", "json")])
+                .query(params)
+        })?)
+    }
+
+    fn fetch_tokens(&self, token_type: &str) -> Result<Tokens, ApiError> {
+        Ok(self
+            .get(&[
+                ("action", "query"),
+                ("meta", "tokens"),
+                ("type", token_type),
+            ])?
+            .error_for_status()?
+            .json::<TokenResponse>()?
+            .query
+            .tokens)
+    }
+
+    /// open a new session (if there are some credentials) and get a new csrf token
+    fn login(&self) -> Result<(), ApiError> {
+        if let Some(credentials) = &self.credentials {
+            log::info!("login as {}", credentials.username);
+            let login_token = self
+                .fetch_tokens("login")?
+                .logintoken
+                .ok_or_else(|| ApiError::LoginError("no login token".to_owned()))?;
+            let res = retry::send(&self.retry, "login", || {
+                self.client
+                    .post(&self.endpoint)
+                    .query(&[("action", "login"), ("format", "json")])
+                    .form(&[
+                        ("lgname", credentials.username.as_str()),
+                        ("lgpassword", credentials.password.as_str()),
+                        ("lgtoken", login_token.as_str()),
+                    ])
+            })?
+            .error_for_status()?
+            .json::<serde_json::Value>()?;
+            if res.pointer("/login/result").and_then(|r| r.as_str()) != Some("Success") {
+                return Err(ApiError::LoginError(
+                    res.pointer("/login/reason")
+                        .or_else(|| res.pointer("/error/info"))
+                        .and_then(|r| r.as_str())
+                        .unwrap_or("unknown reason")
+                        .to_owned(),
+                ));
+            }
+        }
+        *self.token.lock().unwrap() = self
+            .fetch_tokens("csrf")?
+            .csrftoken
+            .ok_or_else(|| ApiError::GenericError("no csrf token".to_owned()))?;
+        Ok(())
+    }
+
+    /// post an edit request to the api and return the body of the response
+    /// The edits rejected because of a transient error are retried.
+    /// If the session has expired, we login again before retrying
+    fn post_edit(
+        &self,
+        params: &[(&str, &str)],
+        form: &[(&str, &str)],
+    ) -> Result<String, ApiError> {
+        let action = params
+            .iter()
+            .find(|(k, _)| *k == "action")
+            .map_or("api edit", |(_, v)| *v);
+        self.retry.run(action, || {
+            let (body, retry_after) = self.post_edit_once(params, form).map_err(Failure::fatal)?;
+            let error_code = serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|res| res.pointer("/error/code")?.as_str().map(|c| c.to_owned()));
+            match error_code {
+                Some(code) if SESSION_ERRORS.contains(&code.as_str()) => {
+                    // a new csrf token is fetched with the new session
+                    self.login().map_err(Failure::fatal)?;
+                    Err(Failure::transient(
+                        ApiError::GenericError(format!("api error '{}'", code)),
+                        Some(Duration::from_secs(0)),
+                    ))
+                }
+                Some(code) if TRANSIENT_ERRORS.contains(&code.as_str()) => Err(Failure::transient(
+                    ApiError::GenericError(format!("api error '{}'", code)),
+                    retry_after,
+                )),
+                _ => Ok(body),
+            }
         })
     }
 
-    fn get(&self) -> reqwest::RequestBuilder {
-        self.client.get(&self.endpoint).query(&[("format", "json")])
+    /// post an edit request, and return the body and the `Retry-After` header of the response
+    fn post_edit_once(
+        &self,
+        params: &[(&str, &str)],
+        form: &[(&str, &str)],
+    ) -> Result<(String, Option<Duration>), ApiError> {
+        let token = self.token.lock().unwrap().clone();
+        let mut form = form.to_vec();
+        form.push(("token", token.as_str()));
+        if self.credentials.is_some() {
+            // the edit is rejected instead of being made anonymously if the session has expired
+            form.push(("assert", "user"));
+        }
+        let maxlag = self.retry.maxlag.map(|m| m.to_string());
+        if let Some(maxlag) = &maxlag {
+            form.push(("maxlag", maxlag.as_str()));
+        }
+        let request = || {
+            self.client
+                .post(&self.endpoint)
+                .query(&[("format", "json")])
+                .query(params)
+                .form(&form)
+        };
+        // sending again a creation that timed out could create the entity twice
+        let mut res = if params.iter().any(|(k, _)| *k == "new") {
+            retry::send_once(&self.retry, "api edit", request)?
+        } else {
+            retry::send(&self.retry, "api edit", request)?
+        }
+        .error_for_status()?;
+
+        log::trace!("Response headers: {:#?}", res);
+        let retry_after = retry::retry_after(&res);
+        let body = res.text()?;
+        log::trace!("Response body: {:#?}", body);
+        Ok((body, retry_after))
     }
 
     /// search for all entities for a given english label
     pub fn get_entity(&self, id: &str) -> Result<entity::Entity, ApiError> {
         let mut res: EntityResponse = self
-            .get()
-            .query(&[("action", "wbgetentities"), ("ids", id)])
-            .send()?
+            .get(&[("action", "wbgetentities"), ("ids", id)])?
             .json()?;
 
         // the id is always here is the api response (even if the object does not exists)
@@ -87,6 +264,25 @@ impl ApiClient {
         if r.missing.is_some() {
             Err(ApiError::EntityNotFound(id.to_owned()))
         } else {
+            let mut properties = HashMap::new();
+            let mut qualifiers = HashMap::new();
+            for (prop_id, claims) in r.claims.unwrap_or_default() {
+                let mut vals = vec![];
+                let mut claims_qualifiers = vec![];
+                for claim in claims {
+                    vals.push(claim.mainsnak.datavalue.into());
+                    claims_qualifiers.push(
+                        claim
+                            .qualifiers
+                            .values()
+                            .flatten()
+                            .filter_map(entity::PropertyValue::from_snak)
+                            .collect(),
+                    );
+                }
+                properties.insert(prop_id.clone(), vals);
+                qualifiers.insert(prop_id, claims_qualifiers);
+            }
             Ok(entity::Entity {
                 id: r.id,
                 label: r
@@ -94,31 +290,13 @@ impl ApiClient {
                     .and_then(|mut l| l.remove("en"))
                     .map(|l| l.value)
                     .ok_or_else(|| ApiError::GenericError("invalid api response".to_owned()))?,
-                properties: r
-                    .claims
-                    .unwrap()
-                    .into_iter()
-                    .map(|(prop_id, claims)| {
-                        let vals = claims
-                            .into_iter()
-                            .map(|claim| {
-                                let data_value = claim.mainsnak.datavalue;
-                                match data_value {
-                                    Datavalue::String(s) => entity::PropertyValue::String(s),
-                                    Datavalue::Item { id } => entity::PropertyValue::Item(id),
-                                    Datavalue::Coord {
-                                        latitude,
-                                        longitude,
-                                    } => entity::PropertyValue::Coord {
-                                        latitude,
-                                        longitude,
-                                    },
-                                }
-                            })
-                            .collect();
-                        Ok((prop_id, vals))
-                    })
-                    .collect::<Result<HashMap<String, Vec<entity::PropertyValue>>, ApiError>>()?,
+                aliases: r
+                    .aliases
+                    .and_then(|mut a| a.remove("en"))
+                    .map(|a| a.into_iter().map(|l| l.value).collect())
+                    .unwrap_or_default(),
+                properties,
+                qualifiers,
             })
         }
     }
@@ -151,24 +329,19 @@ impl ApiClient {
         let claims = serde_json::to_string(&json_claims)?;
 
         log::trace!("claims: {}", claims);
-        let mut res = self
-            .client
-            .post(&self.endpoint)
-            .query(&[
+        let body = self.post_edit(
+            &[
                 ("action", "wbeditentity"),
                 ("new", &object_type.to_string()),
-                ("format", "json"),
-            ])
-            .form(&[("token", &self.token), ("data", &claims)])
-            .send()?
-            .error_for_status()?;
-
-        log::trace!("Response headers: {:#?}", res);
-        let body = res.text()?;
-        log::trace!("Response body: {:#?}", body);
+            ],
+            &[("data", &claims)],
+        )?;
         let res = serde_json::from_str::<ApiResponse>(&body)?;
         match res.content {
-            ApiResponseContent::Entity(entity) => Ok(entity.id),
+            ApiResponseContent::Entity(entity) => {
+                self.track_revision(&entity);
+                Ok(entity.id)
+            }
             ApiResponseContent::Error(err) => {
                 if let Some(message) = err
                     .messages
@@ -220,9 +393,7 @@ impl ApiClient {
 
     pub fn get_label(&self, id: &str) -> Result<String, anyhow::Error> {
         let res = self
-            .get()
-            .query(&[("action", "wbgetentities"), ("ids", id), ("format", "json")])
-            .send()?
+            .get(&[("action", "wbgetentities"), ("ids", id)])?
             .error_for_status()?
             .json::<serde_json::Value>()?;
 
@@ -248,6 +419,121 @@ impl ApiClient {
         self.update_object_claims(entity_id, claims, true)
     }
 
+    /// Set the english label of an entity, and optionally add an english alias
+    pub fn set_label(
+        &self,
+        entity_id: &str,
+        label: &str,
+        alias: Option<&str>,
+    ) -> Result<(), ApiError> {
+        let mut data = json!({
+            "labels": { "en": { "language": "en", "value": label } }
+        });
+        if let Some(alias) = alias {
+            data["aliases"] = json!({ "en": [{ "language": "en", "value": alias, "add": "" }] });
+        }
+        let data = serde_json::to_string(&data)?;
+        let body = self.post_edit(
+            &[("action", "wbeditentity"), ("id", entity_id)],
+            &[("data", &data)],
+        )?;
+        if let ApiResponseContent::Entity(entity) = serde_json::from_str::<ApiResponse>(&body)?
+            .error_for_status()?
+            .content
+        {
+            self.track_revision(&entity);
+        }
+        Ok(())
+    }
+
+    /// Remove the statements of the entity with the same property and main value as the claims
+    pub fn remove_claims(
+        &self,
+        entity_id: &str,
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<(), ApiError> {
+        let removed: Vec<_> = claims
+            .iter()
+            .flatten()
+            .filter_map(entity::PropertyValue::from_claim)
+            .collect();
+        if removed.is_empty() {
+            return Ok(());
+        }
+        // the statements are removed by their ids, so we need to read them first
+        let mut res: EntityResponse = self
+            .get(&[
+                ("action", "wbgetentities"),
+                ("ids", entity_id),
+                ("props", "claims"),
+            ])?
+            .json()?;
+        let statements = res
+            .entities
+            .remove(entity_id)
+            .and_then(|e| e.claims)
+            .ok_or_else(|| ApiError::EntityNotFound(entity_id.to_owned()))?;
+
+        let mut removals = vec![];
+        for (property, claims) in statements {
+            for claim in claims {
+                let value: (String, entity::PropertyValue) =
+                    (property.clone(), claim.mainsnak.datavalue.into());
+                if removed.contains(&value) {
+                    removals.push(json!({ "id": claim.id, "remove": "" }));
+                }
+            }
+        }
+        if removals.is_empty() {
+            return Ok(());
+        }
+
+        let data = serde_json::to_string(&json!({ "claims": removals }))?;
+        log::trace!("removed claims: {}", data);
+        let body = self.post_edit(
+            &[("action", "wbeditentity"), ("id", entity_id)],
+            &[("data", &data)],
+        )?;
+        if let ApiResponseContent::Entity(entity) = serde_json::from_str::<ApiResponse>(&body)?
+            .error_for_status()?
+            .content
+        {
+            self.track_revision(&entity);
+        }
+        Ok(())
+    }
+
+    /// Delete an entity. The user needs to have the right to delete pages
+    pub fn delete_entity(&self, entity_id: &str, reason: &str) -> Result<(), ApiError> {
+        // the deletion is done on the wiki page of the entity, so we need its title
+        let mut res: EntityResponse = self
+            .get(&[
+                ("action", "wbgetentities"),
+                ("ids", entity_id),
+                ("props", "info"),
+            ])?
+            .json()?;
+        let title = res
+            .entities
+            .remove(entity_id)
+            .and_then(|e| e.title)
+            .ok_or_else(|| ApiError::EntityNotFound(entity_id.to_owned()))?;
+
+        let body = self.post_edit(
+            &[("action", "delete")],
+            &[("title", &title), ("reason", reason)],
+        )?;
+        let res = serde_json::from_str::<serde_json::Value>(&body)?;
+        match res.get("error") {
+            Some(err) => Err(ApiError::GenericError(format!(
+                "impossible to delete {}: {}",
+                entity_id,
+                err["info"].as_str().unwrap_or_default()
+            ))),
+            None => Ok(()),
+        }
+    }
+
     fn update_object_claims(
         &self,
         entity_id: &str,
@@ -257,26 +543,17 @@ impl ApiClient {
         let claims: Vec<_> = claims.into_iter().filter_map(|v| v).collect();
         let claims = serde_json::to_string(&json!({ "claims": claims }))?;
         log::trace!("claims: {}", claims);
-        let mut params = vec![
-            ("action", "wbeditentity"),
-            ("id", entity_id),
-            ("format", "json"),
-        ];
+        let mut params = vec![("action", "wbeditentity"), ("id", entity_id)];
         if override_claims {
             params.push(("clear", "true"));
         }
-        let mut res = self
-            .client
-            .post(&self.endpoint)
-            .query(&params)
-            .form(&[("token", &self.token), ("data", &claims)])
-            .send()?
-            .error_for_status()?;
-
-        log::trace!("Response headers: {:#?}", res);
-        let body = res.text()?;
-        log::trace!("Response body: {:#?}", body);
-        serde_json::from_str::<ApiResponse>(&body)?.error_for_status()?;
+        let body = self.post_edit(&params, &[("data", &claims)])?;
+        if let ApiResponseContent::Entity(entity) = serde_json::from_str::<ApiResponse>(&body)?
+            .error_for_status()?
+            .content
+        {
+            self.track_revision(&entity);
+        }
         Ok(())
     }
 }
@@ -292,6 +569,33 @@ pub fn claim(property: &str, datavalue: serde_json::Value) -> Option<serde_json:
         "rank": "normal"
     }))
 }
+
+/// Add some qualifiers to a claim
+/// The qualifiers are built like claims, with `claim_string`, `claim_item`, ...
+/// and only their main snak is kept
+pub fn with_qualifiers(
+    claim: Option<serde_json::Value>,
+    qualifiers: Vec<Option<serde_json::Value>>,
+) -> Option<serde_json::Value> {
+    let mut claim = claim?;
+    let mut snaks = serde_json::Map::new();
+    for qualifier in qualifiers.into_iter().flatten() {
+        let snak = qualifier["mainsnak"].clone();
+        let property = snak["property"].as_str().unwrap_or_default().to_owned();
+        if let Some(values) = snaks
+            .entry(property)
+            .or_insert_with(|| json!([]))
+            .as_array_mut()
+        {
+            values.push(snak);
+        }
+    }
+    if !snaks.is_empty() {
+        claim["qualifiers"] = serde_json::Value::Object(snaks);
+    }
+    Some(claim)
+}
+
 pub fn claim_string(property: &str, value: &str) -> Option<serde_json::Value> {
     let value = value.trim();
     if value.is_empty() {
@@ -312,6 +616,9 @@ pub fn claim_item(property: &str, id: &str) -> Option<serde_json::Value> {
     )
 }
 
+/// Precision (in degrees) of the coordinates of the claims
+pub const COORD_PRECISION: f64 = 0.000_001;
+
 pub fn claim_coord(property: &str, lon: f64, lat: f64) -> Option<serde_json::Value> {
     claim(
         property,
@@ -319,10 +626,24 @@ pub fn claim_coord(property: &str, lon: f64, lat: f64) -> Option<serde_json::Val
             "value": {
                 "latitude": lat,
                 "longitude": lon,
-                "precision": 0.000_001,
+                "precision": COORD_PRECISION,
                 "globe": "http://www.wikidata.org/entity/Q2"
             },
             "type": "globecoordinate",
         }),
     )
 }
+
+pub fn claim_quantity(property: &str, amount: f64) -> Option<serde_json::Value> {
+    claim(
+        property,
+        json!({
+            "value": {
+                // the amount needs to be a string with an explicit sign
+                "amount": format!("{:+}", amount),
+                "unit": "1"
+            },
+            "type": "quantity",
+        }),
+    )
+}
@@ -14,4 +14,6 @@ pub enum ApiError {
     InvalidJsonError(#[from] serde_json::Error),
     #[error("error: {0}")]
     GenericError(String),
+    #[error("impossible to login: {0}")]
+    LoginError(String),
 }
@@ -16,6 +16,26 @@ pub enum Datavalue {
     Item { id: String },
     #[serde(rename = "globecoordinate")]
     Coord { latitude: f64, longitude: f64 },
+    #[serde(rename = "quantity")]
+    Quantity { amount: String },
+}
+
+impl From<Datavalue> for crate::entity::PropertyValue {
+    fn from(datavalue: Datavalue) -> Self {
+        use crate::entity::PropertyValue;
+        match datavalue {
+            Datavalue::String(s) => PropertyValue::String(s),
+            Datavalue::Item { id } => PropertyValue::Item(id),
+            Datavalue::Coord {
+                latitude,
+                longitude,
+            } => PropertyValue::Coord {
+                latitude,
+                longitude,
+            },
+            Datavalue::Quantity { amount } => PropertyValue::Quantity(amount),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -25,7 +45,12 @@ pub struct Snak {
 
 #[derive(Deserialize, Debug)]
 pub struct Claim {
+    /// guid of the statement
+    pub id: String,
     pub mainsnak: Snak,
+    /// the snaks of the qualifiers, by property
+    #[serde(default)]
+    pub qualifiers: HashMap<String, Vec<serde_json::Value>>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -37,8 +62,11 @@ pub struct Label {
 #[derive(Deserialize, Debug)]
 pub struct Entity {
     pub id: String,
+    /// title of the wiki page of the entity (like `Item:Q42`)
+    pub title: Option<String>,
     pub claims: Option<HashMap<String, Vec<Claim>>>,
     pub labels: Option<HashMap<String, Label>>,
+    pub aliases: Option<HashMap<String, Vec<Label>>>,
     pub missing: Option<String>, // if not None, the object does not exists
 }
 
@@ -56,7 +84,8 @@ pub struct SearchResponse {
 
 #[derive(Deserialize, Debug)]
 pub struct Tokens {
-    pub csrftoken: String,
+    pub csrftoken: Option<String>,
+    pub logintoken: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -108,6 +137,8 @@ pub enum ApiResponseContent {
 #[derive(Deserialize, Debug)]
 pub struct InsertEntity {
     pub id: String,
+    /// id of the revision created by the edit
+    pub lastrevid: Option<u64>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -0,0 +1,68 @@
+//! Credentials of the bot account used to edit the wikibase
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Bot password, created with the Special:BotPasswords page of the wiki
+#[derive(Deserialize, Clone)]
+pub struct Credentials {
+    /// Name of the bot, in the form `<user>@<bot name>`
+    pub username: String,
+    pub password: String,
+}
+
+// the password should never end up in the logs
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+/// Authentication cli options, shared by all the binaries
+/// Without any of them, the edits are anonymous
+#[derive(StructOpt, Debug, Default)]
+pub struct AuthOptions {
+    /// Name of the bot used to edit the wikibase, in the form `<user>@<bot name>`
+    #[structopt(long, env = "TOPO_BOT_USERNAME")]
+    pub bot_username: Option<String>,
+
+    /// Password of the bot
+    #[structopt(long, env = "TOPO_BOT_PASSWORD", hide_env_values = true)]
+    pub bot_password: Option<String>,
+
+    /// Toml file with the `username` and `password` of the bot.
+    /// The cli options and environment variables take precedence over this file
+    #[structopt(long, parse(from_os_str))]
+    pub auth_config: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+struct AuthConfig {
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl AuthOptions {
+    pub fn credentials(&self) -> Result<Option<Credentials>, anyhow::Error> {
+        let config = match &self.auth_config {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("impossible to read {:?}", path))?;
+                toml::from_str(&content).with_context(|| format!("invalid config {:?}", path))?
+            }
+            None => AuthConfig::default(),
+        };
+        let username = self.bot_username.clone().or(config.username);
+        let password = self.bot_password.clone().or(config.password);
+        match (username, password) {
+            (Some(username), Some(password)) => Ok(Some(Credentials { username, password })),
+            (None, None) => Ok(None),
+            (Some(_), None) => Err(anyhow::anyhow!("no password given for the bot")),
+            (None, Some(_)) => Err(anyhow::anyhow!("no username given for the bot")),
+        }
+    }
+}
@@ -1,8 +1,15 @@
 pub mod api_client;
 mod api_error;
 mod api_structures;
+pub mod auth;
+pub mod rate_limiter;
+pub mod retry;
+pub mod sparql_builder;
 pub mod sparql_client;
 
 pub use api_client::{ApiClient, ObjectType, PropertyDataType};
 pub use api_error::ApiError;
+pub use auth::{AuthOptions, Credentials};
+pub use rate_limiter::RateLimiter;
+pub use retry::{RetryOptions, RetryPolicy};
 pub use sparql_client::SparqlClient;
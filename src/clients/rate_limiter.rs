@@ -0,0 +1,36 @@
+//! Limit of the number of requests sent to the shared wikibase instance
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Spread the requests evenly so that no more than `requests_per_second` are sent.
+/// The limiter is shared by all the clients and threads of the program
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    /// time from which the next request can be sent
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// `requests_per_second` must be a positive number
+    pub fn new(requests_per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1. / requests_per_second),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until a request can be sent
+    pub fn wait(&self) {
+        let delay = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = std::cmp::max(*next_slot, now);
+            *next_slot = slot + self.interval;
+            slot - now
+        };
+        if delay > Duration::from_secs(0) {
+            std::thread::sleep(delay);
+        }
+    }
+}
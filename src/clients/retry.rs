@@ -0,0 +1,213 @@
+//! Retry of the requests that failed because of a transient error
+use super::rate_limiter::RateLimiter;
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of retries of a request, 0 to never retry
+    pub max_retries: u32,
+    /// Delay before the first retry, it is doubled at each retry
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// `maxlag` parameter sent to the wikibase api, in seconds.
+    /// The api rejects the requests when the replication lag of the database is higher
+    /// see https://www.mediawiki.org/wiki/Manual:Maxlag_parameter
+    pub maxlag: Option<u32>,
+    /// Limit of the number of requests per second, shared by all the clients using this policy
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            maxlag: Some(5),
+            rate_limiter: None,
+        }
+    }
+}
+
+/// Why a request failed
+pub struct Failure<E> {
+    pub error: E,
+    /// the request can be sent again
+    pub transient: bool,
+    /// delay asked by the server before sending the request again
+    pub retry_after: Option<Duration>,
+}
+
+impl<E> Failure<E> {
+    pub fn fatal(error: E) -> Self {
+        Self {
+            error,
+            transient: false,
+            retry_after: None,
+        }
+    }
+
+    pub fn transient(error: E, retry_after: Option<Duration>) -> Self {
+        Self {
+            error,
+            transient: true,
+            retry_after,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt);
+        self.initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |d| d.min(self.max_delay))
+    }
+
+    /// Call `f` until it succeeds, or fails with a non transient error,
+    /// or there have been too many retries.
+    /// `action` is used to log the retries
+    pub fn run<T, E: Display>(
+        &self,
+        action: &str,
+        mut f: impl FnMut() -> Result<T, Failure<E>>,
+    ) -> Result<T, E> {
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Ok(v) => return Ok(v),
+                Err(failure) if failure.transient && attempt < self.max_retries => {
+                    let delay = failure.retry_after.unwrap_or_else(|| self.backoff(attempt));
+                    attempt += 1;
+                    log::warn!(
+                        "{} failed: {}, retry {}/{} in {:?}",
+                        action,
+                        failure.error,
+                        attempt,
+                        self.max_retries,
+                        delay
+                    );
+                    std::thread::sleep(delay);
+                }
+                Err(failure) => return Err(failure.error),
+            }
+        }
+    }
+}
+
+/// Read the `Retry-After` header of a response (only the delay in seconds form is handled)
+pub fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Send a request, the network errors and the 5xx and 429 responses are retried
+pub fn send(
+    policy: &RetryPolicy,
+    action: &str,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> Result<reqwest::Response, reqwest::Error> {
+    send_request(policy, action, true, request)
+}
+
+/// Send a request that must not be processed twice, like the creation of an entity.
+/// After a network error or a 5xx response the request might have been processed anyway,
+/// so only the requests rejected with a 429 response are retried
+pub fn send_once(
+    policy: &RetryPolicy,
+    action: &str,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> Result<reqwest::Response, reqwest::Error> {
+    send_request(policy, action, false, request)
+}
+
+fn send_request(
+    policy: &RetryPolicy,
+    action: &str,
+    idempotent: bool,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> Result<reqwest::Response, reqwest::Error> {
+    policy.run(action, || {
+        if let Some(rate_limiter) = &policy.rate_limiter {
+            rate_limiter.wait();
+        }
+        let response = request().send().map_err(|e| {
+            if idempotent && (e.is_timeout() || e.is_http()) {
+                Failure::transient(e, None)
+            } else {
+                Failure::fatal(e)
+            }
+        })?;
+        let status = response.status();
+        if (idempotent && status.is_server_error())
+            || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        {
+            let delay = retry_after(&response);
+            Err(Failure::transient(
+                response.error_for_status().expect_err("error status"),
+                delay,
+            ))
+        } else {
+            Ok(response)
+        }
+    })
+}
+
+/// Retry cli options, shared by all the binaries
+#[derive(StructOpt, Debug)]
+pub struct RetryOptions {
+    /// Maximum number of retries of a failed request
+    #[structopt(long, default_value = "5")]
+    pub max_retries: u32,
+
+    /// Delay before the first retry of a failed request, in milliseconds.
+    /// The delay is doubled at each retry
+    #[structopt(long, default_value = "1000")]
+    pub retry_delay: u64,
+
+    /// Maximum delay between two retries, in milliseconds
+    #[structopt(long, default_value = "60000")]
+    pub max_retry_delay: u64,
+
+    /// `maxlag` parameter sent to the wikibase api, in seconds
+    #[structopt(long, default_value = "5")]
+    pub maxlag: u32,
+
+    /// Maximum number of requests sent per second, retries included.
+    /// Unlimited by default
+    #[structopt(long)]
+    pub max_requests_per_second: Option<f64>,
+}
+
+impl RetryOptions {
+    pub fn policy(&self) -> Result<RetryPolicy, anyhow::Error> {
+        let rate_limiter = match self.max_requests_per_second {
+            // the delay between two requests must be a valid duration
+            Some(limit) if !(limit > 0. && (1. / limit) < u64::MAX as f64) => {
+                return Err(anyhow::anyhow!(
+                    "invalid maximum number of requests per second {}, it must be a positive number",
+                    limit
+                ))
+            }
+            Some(limit) => Some(Arc::new(RateLimiter::new(limit))),
+            None => None,
+        };
+        Ok(RetryPolicy {
+            max_retries: self.max_retries,
+            initial_delay: Duration::from_millis(self.retry_delay),
+            max_delay: Duration::from_millis(self.max_retry_delay),
+            maxlag: Some(self.maxlag),
+            rate_limiter,
+        })
+    }
+}
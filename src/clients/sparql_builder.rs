@@ -0,0 +1,327 @@
+//! Typed builder for the where clauses of the sparql queries
+//!
+//! All the values are escaped, so a gtfs id or a label can be used safely in a query
+//! ```
+//! use transit_topo::clients::sparql_builder::{literal, var, wd, wdt, WhereClause};
+//! let clause = WhereClause::new()
+//!     .triple(var("route"), wdt("P6"), wd("Q12"))
+//!     .triple(var("route"), wdt("P2"), literal("a \"quoted\" id"));
+//! assert_eq!(
+//!     clause.to_string(),
+//!     r#"?route wdt:P6 wd:Q12 . ?route wdt:P2 "a \"quoted\" id" ."#
+//! );
+//! ```
+use itertools::Itertools;
+use std::fmt;
+
+/// A term of a triple pattern
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    /// A variable, like `?route`
+    Var(String),
+    /// A full IRI, like `<http://www.wikidata.org/entity/Q2>`
+    Iri(String),
+    /// A prefixed name, like `wdt:P1`
+    Prefixed { prefix: String, local: String },
+    /// A string literal, with an optional language tag, like `"bob"@en`
+    Literal { value: String, lang: Option<String> },
+}
+
+pub fn var(name: &str) -> Term {
+    Term::Var(name.trim_start_matches('?').to_owned())
+}
+
+pub fn iri(value: &str) -> Term {
+    Term::Iri(value.to_owned())
+}
+
+pub fn prefixed(prefix: &str, local: &str) -> Term {
+    Term::Prefixed {
+        prefix: prefix.to_owned(),
+        local: local.to_owned(),
+    }
+}
+
+/// An entity (item or property), like `wd:Q12`
+pub fn wd(id: &str) -> Term {
+    prefixed("wd", id)
+}
+
+/// The direct value of a property, like `wdt:P1`
+pub fn wdt(id: &str) -> Term {
+    prefixed("wdt", id)
+}
+
+pub fn literal(value: &str) -> Term {
+    Term::Literal {
+        value: value.to_owned(),
+        lang: None,
+    }
+}
+
+pub fn lang_literal(value: &str, lang: &str) -> Term {
+    Term::Literal {
+        value: value.to_owned(),
+        lang: Some(lang.to_owned()),
+    }
+}
+
+/// A term written by a user that cannot be read
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("invalid term {term}: {reason}")]
+pub struct InvalidTerm {
+    pub term: String,
+    pub reason: &'static str,
+}
+
+impl Term {
+    /// Read a term written by a user, like `"bob"@en`, `<http://...>`, `wd:Q12` or `?item`.
+    /// A value in none of those forms is read as a string literal
+    pub fn parse(value: &str) -> Result<Term, InvalidTerm> {
+        let value = value.trim();
+        let invalid = |reason| InvalidTerm {
+            term: value.to_owned(),
+            reason,
+        };
+        if value.starts_with('?') {
+            Ok(var(value))
+        } else if value.starts_with('<') && value.ends_with('>') && value.len() > 1 {
+            Ok(iri(&value[1..value.len() - 1]))
+        } else if value.starts_with('"') {
+            // the content of the literal is unescaped, it is escaped again in the query
+            let mut content = String::new();
+            let mut chars = value.char_indices().skip(1);
+            let end = loop {
+                match chars.next() {
+                    None => return Err(invalid("the literal has no closing quote")),
+                    Some((i, '"')) => break i,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => content.push('\n'),
+                        Some((_, 'r')) => content.push('\r'),
+                        Some((_, 't')) => content.push('\t'),
+                        Some((_, c)) if c == '"' || c == '\'' || c == '\\' => content.push(c),
+                        _ => return Err(invalid("invalid escape sequence in the literal")),
+                    },
+                    Some((_, c)) => content.push(c),
+                }
+            };
+            match &value[end + 1..] {
+                "" => Ok(literal(&content)),
+                rest => match rest.strip_prefix('@') {
+                    Some(lang)
+                        if !lang.is_empty()
+                            && lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+                    {
+                        Ok(lang_literal(&content, lang))
+                    }
+                    Some(_) => Err(invalid("the language tag of the literal is invalid")),
+                    None => Err(invalid("there is some text after the closing quote")),
+                },
+            }
+        } else {
+            match value.find(':') {
+                Some(i) if is_prefix(&value[..i]) && is_local_name(&value[i + 1..]) => {
+                    Ok(prefixed(&value[..i], &value[i + 1..]))
+                }
+                _ => Ok(literal(value)),
+            }
+        }
+    }
+}
+
+/// The name is a valid prefix of a prefixed name (`PN_PREFIX` in the sparql grammar, or empty)
+fn is_prefix(name: &str) -> bool {
+    match name.chars().next() {
+        None => true,
+        Some(first) => {
+            first.is_alphabetic()
+                && !name.ends_with('.')
+                && name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+        }
+    }
+}
+
+/// The name is a valid local part of a prefixed name, without escaped characters
+/// (a simplified `PN_LOCAL` of the sparql grammar)
+fn is_local_name(name: &str) -> bool {
+    match name.chars().next() {
+        None => true,
+        Some(first) => {
+            (first.is_alphanumeric() || first == '_' || first == ':')
+                && !name.ends_with('.')
+                && name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.' || c == ':')
+        }
+    }
+}
+
+/// Escape a string to be used in a double quoted literal
+fn escape_literal(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// percent-encode a character in an IRI or a prefixed name
+fn percent_encode(c: char, out: &mut String) {
+    let mut buf = [0; 4];
+    for b in c.encode_utf8(&mut buf).bytes() {
+        out.push_str(&format!("%{:02X}", b));
+    }
+}
+
+/// The characters forbidden in an IRI are percent-encoded
+fn escape_iri(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\' => percent_encode(c, &mut escaped),
+            c if c <= ' ' => percent_encode(c, &mut escaped),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The special characters of the local part of a prefixed name are escaped
+fn escape_local_name(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '-' => escaped.push(c),
+            '~' | '.' | '!' | '$' | '&' | '\'' | '(' | ')' | '*' | '+' | ',' | ';' | '=' | '/'
+            | '?' | '#' | '@' | '%' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => percent_encode(c, &mut escaped),
+        }
+    }
+    escaped
+}
+
+/// Only keep the characters valid in a name (variable, prefix or language tag)
+fn sanitize_name(value: &str, extra: char) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == extra)
+        .collect()
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Term::Var(name) => write!(f, "?{}", sanitize_name(name, '_')),
+            Term::Iri(value) => write!(f, "<{}>", escape_iri(value)),
+            Term::Prefixed { prefix, local } => write!(
+                f,
+                "{}:{}",
+                sanitize_name(prefix, '_'),
+                escape_local_name(local)
+            ),
+            Term::Literal { value, lang: None } => write!(f, "\"{}\"", escape_literal(value)),
+            Term::Literal {
+                value,
+                lang: Some(lang),
+            } => write!(
+                f,
+                "\"{}\"@{}",
+                escape_literal(value),
+                sanitize_name(lang, '-')
+            ),
+        }
+    }
+}
+
+/// An expression of a FILTER
+#[derive(Debug, Clone)]
+pub enum Expression {
+    Equals(Term, Term),
+    NotEquals(Term, Term),
+    Exists(WhereClause),
+    NotExists(WhereClause),
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Equals(a, b) => write!(f, "{} = {}", a, b),
+            Expression::NotEquals(a, b) => write!(f, "{} != {}", a, b),
+            Expression::Exists(clause) => write!(f, "EXISTS {{ {} }}", clause),
+            Expression::NotExists(clause) => write!(f, "NOT EXISTS {{ {} }}", clause),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    Triple(Term, Term, Term),
+    Filter(Expression),
+    Optional(WhereClause),
+    Values(Term, Vec<Term>),
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Triple(s, p, o) => write!(f, "{} {} {} .", s, p, o),
+            Pattern::Filter(expression) => write!(f, "FILTER ({})", expression),
+            Pattern::Optional(clause) => write!(f, "OPTIONAL {{ {} }}", clause),
+            Pattern::Values(variable, values) => {
+                write!(f, "VALUES {} {{ {} }}", variable, values.iter().format(" "))
+            }
+        }
+    }
+}
+
+/// The patterns of a where clause
+#[derive(Debug, Clone, Default)]
+pub struct WhereClause {
+    patterns: Vec<Pattern>,
+}
+
+impl WhereClause {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn triple(mut self, subject: Term, predicate: Term, object: Term) -> Self {
+        self.patterns
+            .push(Pattern::Triple(subject, predicate, object));
+        self
+    }
+
+    pub fn filter(mut self, expression: Expression) -> Self {
+        self.patterns.push(Pattern::Filter(expression));
+        self
+    }
+
+    pub fn optional(mut self, clause: WhereClause) -> Self {
+        self.patterns.push(Pattern::Optional(clause));
+        self
+    }
+
+    /// Restrict a variable to a list of values
+    pub fn values(mut self, variable: Term, values: Vec<Term>) -> Self {
+        self.patterns.push(Pattern::Values(variable, values));
+        self
+    }
+}
+
+impl fmt::Display for WhereClause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.patterns.iter().format(" "))
+    }
+}
@@ -1,3 +1,5 @@
+use super::retry::{self, RetryPolicy};
+use super::sparql_builder::{Term, WhereClause};
 use itertools::Itertools;
 use std::collections::HashMap;
 
@@ -11,6 +13,7 @@ pub fn read_id_from_url(url: &str) -> Option<String> {
 pub struct SparqlClient {
     client: reqwest::Client,
     endpoint: String,
+    retry: RetryPolicy,
 }
 
 impl SparqlClient {
@@ -19,18 +22,23 @@ impl SparqlClient {
         Self {
             client: reqwest::Client::new(),
             endpoint: endpoint.to_owned(),
+            retry: RetryPolicy::default(),
         }
     }
 
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
     fn query(&self, query: &str) -> Result<serde_json::Value, anyhow::Error> {
         log::debug!("Sparql query: {}", query);
-        let response = self
-            .client
-            .get(&self.endpoint)
-            .query(&[("format", "json"), ("query", query)])
-            .send()?
-            .error_for_status()?
-            .text()?;
+        let response = retry::send(&self.retry, "sparql query", || {
+            self.client
+                .get(&self.endpoint)
+                .query(&[("format", "json"), ("query", query)])
+        })?
+        .error_for_status()?
+        .text()?;
         log::trace!("Query response: {:?}", response);
         Ok(serde_json::from_str(&response)?)
     }
@@ -38,10 +46,50 @@ impl SparqlClient {
     pub fn sparql(
         &self,
         variables: &[&str],
-        where_clause: &str,
+        where_clause: &WhereClause,
+    ) -> Result<Vec<HashMap<String, String>>, anyhow::Error> {
+        self.sparql_with_modifiers(variables, where_clause, "")
+    }
+
+    /// Run the query by pages of `page_size` results
+    /// The results need to be ordered (by `order_by`) to have a stable pagination,
+    /// so `order_by` must give a total order of the rows: all the projected variables
+    /// that are not determined by the others
+    pub fn sparql_paged(
+        &self,
+        variables: &[&str],
+        where_clause: &WhereClause,
+        order_by: &[Term],
+        page_size: usize,
+    ) -> Result<Vec<HashMap<String, String>>, anyhow::Error> {
+        let mut result = Vec::new();
+        loop {
+            let page = self.sparql_with_modifiers(
+                variables,
+                where_clause,
+                &format!(
+                    "ORDER BY {} LIMIT {} OFFSET {}",
+                    order_by.iter().format(" "),
+                    page_size,
+                    result.len()
+                ),
+            )?;
+            let is_last_page = page.len() < page_size;
+            result.extend(page);
+            if is_last_page {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn sparql_with_modifiers(
+        &self,
+        variables: &[&str],
+        where_clause: &WhereClause,
+        modifiers: &str,
     ) -> Result<Vec<HashMap<String, String>>, anyhow::Error> {
         let vars = variables.iter().format(" ");
-        let query = format!("SELECT {} WHERE {{ {} SERVICE wikibase:label {{ bd:serviceParam wikibase:language \"en\". }} }}", vars, where_clause);
+        let query = format!("SELECT {} WHERE {{ {} SERVICE wikibase:label {{ bd:serviceParam wikibase:language \"en\". }} }} {}", vars, where_clause, modifiers);
         let res = self.query(&query)?;
 
         let mut result = Vec::new();
@@ -1,6 +1,7 @@
 use crate::clients::api_client::{claim_item, claim_string};
+use crate::clients::sparql_builder::{literal, var, wdt, WhereClause};
 use crate::clients::{
-    sparql_client, ApiClient, ApiError, ObjectType, PropertyDataType, SparqlClient,
+    sparql_client, ApiClient, ApiError, Credentials, ObjectType, PropertyDataType, SparqlClient,
 };
 use crate::known_entities::{EntitiesId, Items, Properties};
 use anyhow::Error;
@@ -13,8 +14,12 @@ pub struct Client {
 }
 
 impl Client {
-    pub fn new(api_endpoint: &str, sparql_enpoint: &str) -> Result<Self, Error> {
-        let api = ApiClient::new(api_endpoint)?;
+    pub fn new(
+        api_endpoint: &str,
+        sparql_enpoint: &str,
+        credentials: Option<Credentials>,
+    ) -> Result<Self, Error> {
+        let api = ApiClient::with_credentials(api_endpoint, credentials)?;
 
         let topo_id_id =
             get_or_create_property_impl(&api, "Topo tools id", PropertyDataType::String, None)?;
@@ -29,11 +34,7 @@ impl Client {
 fn get_id_by_topo_id(client: &Client, topo_id: &str) -> Result<Option<String>, anyhow::Error> {
     let items = client.sparql.sparql(
         &["?item"],
-        &format!(
-            r#"?item wdt:{topo_id_prop} "{topo_id}"."#,
-            topo_id = topo_id,
-            topo_id_prop = client.topo_id_id,
-        ),
+        &WhereClause::new().triple(var("item"), wdt(&client.topo_id_id), literal(topo_id)),
     )?;
     match items.as_slice() {
         [] => Ok(None),
@@ -97,8 +98,12 @@ fn get_or_create_property_impl<'a>(
     }
 }
 
-pub fn initial_populate(api_endpoint: &str, sparql_endpoint: &str) -> Result<EntitiesId, Error> {
-    let client = Client::new(api_endpoint, sparql_endpoint)?;
+pub fn initial_populate(
+    api_endpoint: &str,
+    sparql_endpoint: &str,
+    credentials: Option<Credentials>,
+) -> Result<EntitiesId, Error> {
+    let client = Client::new(api_endpoint, sparql_endpoint, credentials)?;
 
     let create_prop = |label, prop_type| get_or_create_property(&client, label, prop_type);
 
@@ -118,6 +123,17 @@ pub fn initial_populate(api_endpoint: &str, sparql_endpoint: &str) -> Result<Ent
             ],
         )
     };
+    let wheelchair_accessibility = get_or_create_item(&client, "Wheelchair accessibility", &[])?;
+    let create_accessibility = |label, id| {
+        get_or_create_item(
+            &client,
+            label,
+            &[
+                claim_item(&instance_of, &wheelchair_accessibility),
+                claim_string(&gtfs_id, id),
+            ],
+        )
+    };
     let create_stop = |label, id| get_or_create_item(&client, label, &[claim_string(&gtfs_id, id)]);
 
     let known_entities = EntitiesId {
@@ -128,17 +144,54 @@ pub fn initial_populate(api_endpoint: &str, sparql_endpoint: &str) -> Result<Ent
             gtfs_short_name: create_prop("GTFS short name", PropertyDataType::String)?,
             gtfs_long_name: create_prop("GTFS long name", PropertyDataType::String)?,
             gtfs_name: create_prop("GTFS name", PropertyDataType::String)?,
+            gtfs_description: create_prop("GTFS description", PropertyDataType::String)?,
             gtfs_id: gtfs_id.to_owned(),
             has_physical_mode: create_prop("Has physical mode", PropertyDataType::Item)?,
             first_seen_in: create_prop("First seen in", PropertyDataType::Item)?,
+            seen_in: create_prop("Seen in", PropertyDataType::Item)?,
+            no_longer_present_since: create_prop(
+                "No longer present since",
+                PropertyDataType::Item,
+            )?,
             data_source: create_prop("Data source", PropertyDataType::Item)?,
             source: create_prop("Source", PropertyDataType::String)?, //Link to the raw file
             file_format: create_prop("File format", PropertyDataType::String)?,
             sha_256: create_prop("sha_256", PropertyDataType::String)?,
+            import_status: create_prop("Import status", PropertyDataType::String)?,
+            route_count: create_prop("Route count", PropertyDataType::Quantity)?,
+            stop_count: create_prop("Stop count", PropertyDataType::Quantity)?,
+            relation_count: create_prop("Relation count", PropertyDataType::Quantity)?,
+            warning_count: create_prop("Warning count", PropertyDataType::Quantity)?,
             tool_version: create_prop("Tool version", PropertyDataType::String)?,
             part_of: create_prop("Part of", PropertyDataType::Item)?,
             connecting_line: create_prop("Connecting line", PropertyDataType::Item)?,
             coordinate_location: create_prop("Coordinate location", PropertyDataType::Coord)?,
+            operated_by: create_prop("Operated by", PropertyDataType::Item)?,
+            url: create_prop("URL", PropertyDataType::Url)?,
+            timezone: create_prop("Timezone", PropertyDataType::String)?,
+            phone: create_prop("Phone", PropertyDataType::String)?,
+            transfer_to: create_prop("Transfer to", PropertyDataType::Item)?,
+            gtfs_transfer_type: create_prop("GTFS transfer type", PropertyDataType::String)?,
+            min_transfer_time: create_prop("Min transfer time", PropertyDataType::Quantity)?,
+            on_level: create_prop("On level", PropertyDataType::Item)?,
+            level_index: create_prop("Level index", PropertyDataType::Quantity)?,
+            pathway_to: create_prop("Pathway to", PropertyDataType::Item)?,
+            gtfs_pathway_mode: create_prop("GTFS pathway mode", PropertyDataType::String)?,
+            gtfs_is_bidirectional: create_prop("GTFS is bidirectional", PropertyDataType::String)?,
+            length: create_prop("Length", PropertyDataType::Quantity)?,
+            traversal_time: create_prop("Traversal time", PropertyDataType::Quantity)?,
+            color: create_prop("Color", PropertyDataType::String)?,
+            text_color: create_prop("Text color", PropertyDataType::String)?,
+            sort_order: create_prop("Sort order", PropertyDataType::Quantity)?,
+            gtfs_code: create_prop("GTFS code", PropertyDataType::String)?,
+            platform_code: create_prop("Platform code", PropertyDataType::String)?,
+            gtfs_zone_id: create_prop("GTFS zone id", PropertyDataType::String)?,
+            wheelchair_boarding: create_prop("Wheelchair boarding", PropertyDataType::Item)?,
+            pattern_of: create_prop("Pattern of", PropertyDataType::Item)?,
+            gtfs_direction_id: create_prop("GTFS direction id", PropertyDataType::String)?,
+            gtfs_headsign: create_prop("GTFS headsign", PropertyDataType::String)?,
+            has_stop: create_prop("Has stop", PropertyDataType::Item)?,
+            stop_sequence: create_prop("Stop sequence", PropertyDataType::Quantity)?,
         },
         items: Items {
             physical_mode: physical_mode.to_owned(),
@@ -157,6 +210,13 @@ pub fn initial_populate(api_endpoint: &str, sparql_endpoint: &str) -> Result<Ent
             stop_entrance: create_stop("Stop entrance", "2")?,
             stop_generic_node: create_stop("Stop generic node", "3")?,
             stop_boarding_area: create_stop("Stop boarding area", "4")?,
+            agency: get_or_create_item(&client, "Agency/Operator", &[])?,
+            level: get_or_create_item(&client, "Level", &[])?,
+            route_pattern: get_or_create_item(&client, "Route pattern", &[])?,
+            wheelchair_accessibility: wheelchair_accessibility.to_owned(),
+            unknown_accessibility: create_accessibility("Unknown accessibility", "0")?,
+            accessible: create_accessibility("Accessible", "1")?,
+            not_accessible: create_accessibility("Not accessible", "2")?,
         },
     };
 
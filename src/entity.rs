@@ -1,8 +1,13 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PropertyValue {
     String(String),
     Item(String),
-    Coord { latitude: f64, longitude: f64 },
+    Coord {
+        latitude: f64,
+        longitude: f64,
+    },
+    /// The amount of a quantity, as given by the api (like "+42")
+    Quantity(String),
 }
 
 impl PropertyValue {
@@ -10,10 +15,68 @@ impl PropertyValue {
         match self {
             PropertyValue::String(e) => e,
             PropertyValue::Item(e) => e,
+            PropertyValue::Quantity(e) => e,
             // Note: this method is used only in tests, we can panic
             PropertyValue::Coord { .. } => panic!("unable to convert coord to string"),
         }
     }
+
+    /// The values are the same. The coordinates returned by wikibase are not exactly the ones
+    /// sent, so they are compared at the precision of the claims
+    pub fn is_same(&self, other: &PropertyValue) -> bool {
+        use crate::clients::api_client::COORD_PRECISION;
+        match (self, other) {
+            (
+                PropertyValue::Coord {
+                    latitude,
+                    longitude,
+                },
+                PropertyValue::Coord {
+                    latitude: other_latitude,
+                    longitude: other_longitude,
+                },
+            ) => {
+                (latitude - other_latitude).abs() < COORD_PRECISION
+                    && (longitude - other_longitude).abs() < COORD_PRECISION
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Read the property and the main value of a claim in the format of the wikibase api
+    pub fn from_claim(claim: &serde_json::Value) -> Option<(String, PropertyValue)> {
+        Self::from_snak(&claim["mainsnak"])
+    }
+
+    /// Read the property and the value of a snak (the main value or a qualifier of a claim)
+    pub fn from_snak(snak: &serde_json::Value) -> Option<(String, PropertyValue)> {
+        let value = &snak["datavalue"]["value"];
+        let value = match snak["datavalue"]["type"].as_str()? {
+            "string" => PropertyValue::String(value.as_str()?.to_owned()),
+            "wikibase-entityid" => PropertyValue::Item(value["id"].as_str()?.to_owned()),
+            "globecoordinate" => PropertyValue::Coord {
+                latitude: value["latitude"].as_f64()?,
+                longitude: value["longitude"].as_f64()?,
+            },
+            "quantity" => PropertyValue::Quantity(value["amount"].as_str()?.to_owned()),
+            _ => return None,
+        };
+        Some((snak["property"].as_str()?.to_owned(), value))
+    }
+
+    /// Build a claim of the property with this value
+    pub fn to_claim(&self, property: &str) -> Option<serde_json::Value> {
+        use crate::clients::api_client::{claim_coord, claim_item, claim_quantity, claim_string};
+        match self {
+            PropertyValue::String(s) => claim_string(property, s),
+            PropertyValue::Item(id) => claim_item(property, id),
+            PropertyValue::Coord {
+                latitude,
+                longitude,
+            } => claim_coord(property, *longitude, *latitude),
+            PropertyValue::Quantity(amount) => claim_quantity(property, amount.parse().ok()?),
+        }
+    }
 }
 
 impl std::ops::Deref for PropertyValue {
@@ -24,10 +87,58 @@ impl std::ops::Deref for PropertyValue {
     }
 }
 
+/// Read the qualifiers of a claim in the format of the wikibase api
+pub fn claim_qualifiers(claim: &serde_json::Value) -> Vec<(String, PropertyValue)> {
+    claim["qualifiers"]
+        .as_object()
+        .into_iter()
+        .flat_map(|qualifiers| qualifiers.values())
+        .flat_map(|snaks| snaks.as_array().into_iter().flatten())
+        .filter_map(PropertyValue::from_snak)
+        .collect()
+}
+
 /// Simple representation of a wikibase entity
 #[derive(Debug, Clone)]
 pub struct Entity {
     pub id: String,
     pub properties: std::collections::HashMap<String, Vec<PropertyValue>>,
+    /// qualifiers of the claims, by property, in the order of the values of `properties`
+    pub qualifiers: std::collections::HashMap<String, Vec<Vec<(String, PropertyValue)>>>,
     pub label: String,
+    /// english aliases of the entity
+    pub aliases: Vec<String>,
+}
+
+impl Entity {
+    /// The entity already has a claim with the property and the value of `claim`,
+    /// and with the same `key_qualifier` (like the gtfs id distinguishing 2 pathways between the same stops)
+    pub fn has_claim(&self, claim: &serde_json::Value, key_qualifier: &str) -> bool {
+        let (property, value) = match PropertyValue::from_claim(claim) {
+            Some(property_value) => property_value,
+            None => return false,
+        };
+        let key = |qualifiers: &[(String, PropertyValue)]| {
+            qualifiers
+                .iter()
+                .filter(|(qualifier, _)| qualifier == key_qualifier)
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>()
+        };
+        let claim_key = key(&claim_qualifiers(claim));
+        let qualifiers = self.qualifiers.get(&property);
+        self.properties
+            .get(&property)
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .any(|(i, existing)| {
+                existing.is_same(&value)
+                    && key(qualifiers
+                        .and_then(|q| q.get(i))
+                        .map(Vec::as_slice)
+                        .unwrap_or(&[]))
+                        == claim_key
+            })
+    }
 }
@@ -0,0 +1,267 @@
+//! Reading of the optional GTFS files that are not handled by gtfs_structures
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Read an optional field.
+/// An invalid value is ignored with a warning, instead of rejecting the whole file
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) => match value.parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(e) => {
+                log::warn!(
+                    "ignoring the invalid value “{}” of an optional field: {}",
+                    value,
+                    e
+                );
+                Ok(None)
+            }
+        },
+    }
+}
+
+/// A line of the transfers.txt file
+#[derive(Deserialize, Debug, Clone)]
+pub struct Transfer {
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    #[serde(default, deserialize_with = "lenient")]
+    pub transfer_type: Option<u8>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub min_transfer_time: Option<u32>,
+}
+
+/// A line of the levels.txt file
+#[derive(Deserialize, Debug, Clone)]
+pub struct Level {
+    pub level_id: String,
+    pub level_index: f64,
+    pub level_name: Option<String>,
+}
+
+/// A line of the pathways.txt file
+#[derive(Deserialize, Debug, Clone)]
+pub struct Pathway {
+    pub pathway_id: String,
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    pub pathway_mode: u8,
+    pub is_bidirectional: u8,
+    #[serde(default, deserialize_with = "lenient")]
+    pub length: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub traversal_time: Option<u32>,
+}
+
+/// The details of a route, read from the routes.txt file
+#[derive(Deserialize, Debug, Clone)]
+pub struct RouteDetails {
+    pub route_id: String,
+    pub route_desc: Option<String>,
+    pub route_url: Option<String>,
+    pub route_color: Option<String>,
+    pub route_text_color: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub route_sort_order: Option<u32>,
+}
+
+/// The details of a stop, read from the stops.txt file
+#[derive(Deserialize, Debug, Clone)]
+pub struct StopDetails {
+    pub stop_id: String,
+    pub stop_code: Option<String>,
+    pub stop_desc: Option<String>,
+    pub stop_url: Option<String>,
+    pub stop_timezone: Option<String>,
+    pub zone_id: Option<String>,
+    pub platform_code: Option<String>,
+    pub level_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub wheelchair_boarding: Option<u8>,
+}
+
+/// A distinct ordered sequence of stops served by the trips of a route (a journey pattern)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutePattern {
+    pub route_id: String,
+    /// `direction_id` of the trips, "0" or "1"
+    pub direction_id: Option<String>,
+    /// headsign of the first trip (by trip id) having one
+    pub headsign: Option<String>,
+    /// gtfs id of the stops, in the order of the trips
+    pub stop_ids: Vec<String>,
+}
+
+impl RoutePattern {
+    /// Identifier of the pattern, stable between the imports of a producer's GTFS.
+    /// Patterns have no id in the GTFS, so it is made of the route id
+    /// and of a hash of the direction and of the stops
+    pub fn gtfs_id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input(self.direction_id.as_deref().unwrap_or_default().as_bytes());
+        for stop_id in &self.stop_ids {
+            // the separator prevents different sequences from having the same hash
+            hasher.input(b"\n");
+            hasher.input(stop_id.as_bytes());
+        }
+        let hash = format!("{:x}", hasher.result());
+        format!("{}:{}", self.route_id, &hash[..12])
+    }
+}
+
+/// Compute the distinct patterns of the trips, sorted by route, direction and stops
+pub fn route_patterns(trips: &HashMap<String, gtfs_structures::Trip>) -> Vec<RoutePattern> {
+    let mut trips: Vec<_> = trips.values().collect();
+    trips.sort_by(|a, b| a.id.cmp(&b.id));
+    let mut patterns = BTreeMap::new();
+    for trip in trips {
+        let direction_id = trip.direction_id.as_ref().map(|d| match d {
+            gtfs_structures::DirectionType::Outbound => "0".to_owned(),
+            gtfs_structures::DirectionType::Inbound => "1".to_owned(),
+        });
+        let mut stop_times: Vec<_> = trip.stop_times.iter().collect();
+        stop_times.sort_by_key(|st| st.stop_sequence);
+        let stop_ids: Vec<_> = stop_times.iter().map(|st| st.stop.id.clone()).collect();
+        if stop_ids.is_empty() {
+            continue;
+        }
+        let headsign = trip
+            .trip_headsign
+            .as_ref()
+            .filter(|h| !h.trim().is_empty())
+            .cloned();
+        let pattern = patterns
+            .entry((
+                trip.route_id.clone(),
+                direction_id.clone(),
+                stop_ids.clone(),
+            ))
+            .or_insert_with(|| RoutePattern {
+                route_id: trip.route_id.clone(),
+                direction_id,
+                headsign: None,
+                stop_ids,
+            });
+        if pattern.headsign.is_none() {
+            pattern.headsign = headsign;
+        }
+    }
+    patterns.into_values().collect()
+}
+
+/// Read the content of a file of the GTFS, the GTFS can be a zip or a directory
+/// Since the file are optional, an empty content is returned if the file is not in the GTFS
+fn read_file(gtfs_path: &str, file_name: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    let path = Path::new(gtfs_path);
+    let mut content = Vec::new();
+    if path.is_dir() {
+        let file_path = path.join(file_name);
+        if !file_path.exists() {
+            return Ok(None);
+        }
+        std::fs::File::open(&file_path)
+            .with_context(|| format!("impossible to open {:?}", file_path))?
+            .read_to_end(&mut content)?;
+    } else {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("impossible to open {}", gtfs_path))?;
+        let mut archive = zip::ZipArchive::new(file)?;
+        // the files can be in a sub directory of the archive
+        let index = (0..archive.len()).find(|i| {
+            archive
+                .by_index(*i)
+                .map(|f| f.name() == file_name || f.name().ends_with(&format!("/{}", file_name)))
+                .unwrap_or(false)
+        });
+        match index {
+            Some(i) => archive.by_index(i)?.read_to_end(&mut content)?,
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(content))
+}
+
+fn read_objs<T: DeserializeOwned>(
+    gtfs_path: &str,
+    file_name: &str,
+) -> Result<Vec<T>, anyhow::Error> {
+    let content = match read_file(gtfs_path, file_name)? {
+        Some(c) => c,
+        None => {
+            log::debug!("no file {} in the gtfs", file_name);
+            return Ok(vec![]);
+        }
+    };
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(content.as_slice())
+        .deserialize()
+        .collect::<Result<Vec<T>, _>>()
+        .with_context(|| format!("impossible to read {}", file_name))
+}
+
+/// Compute the sha256 of a GTFS
+/// For a zip it is the sha256 of the file, like the one given by gtfs_structures.
+/// For a directory, the name and the content of all the files are hashed, in alphabetical order
+pub fn sha256(gtfs_path: &str) -> Result<String, anyhow::Error> {
+    let path = Path::new(gtfs_path);
+    let mut hasher = Sha256::new();
+    if path.is_dir() {
+        let mut files = std::fs::read_dir(path)
+            .with_context(|| format!("impossible to read {}", gtfs_path))?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.retain(|f| f.is_file());
+        files.sort();
+        for file in files {
+            hasher.input(
+                file.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .as_bytes(),
+            );
+            hasher.input(
+                &std::fs::read(&file).with_context(|| format!("impossible to read {:?}", file))?,
+            );
+        }
+    } else {
+        hasher.input(
+            &std::fs::read(path).with_context(|| format!("impossible to read {}", gtfs_path))?,
+        );
+    }
+    Ok(format!("{:x}", hasher.result()))
+}
+
+pub fn read_transfers(gtfs_path: &str) -> Result<Vec<Transfer>, anyhow::Error> {
+    read_objs(gtfs_path, "transfers.txt")
+}
+
+pub fn read_levels(gtfs_path: &str) -> Result<Vec<Level>, anyhow::Error> {
+    read_objs(gtfs_path, "levels.txt")
+}
+
+pub fn read_pathways(gtfs_path: &str) -> Result<Vec<Pathway>, anyhow::Error> {
+    read_objs(gtfs_path, "pathways.txt")
+}
+
+pub fn read_stop_details(gtfs_path: &str) -> Result<Vec<StopDetails>, anyhow::Error> {
+    read_objs(gtfs_path, "stops.txt")
+}
+
+pub fn read_route_details(gtfs_path: &str) -> Result<Vec<RouteDetails>, anyhow::Error> {
+    read_objs(gtfs_path, "routes.txt")
+}
@@ -0,0 +1,235 @@
+//! Description of what an import did, or would do in dry-run mode
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Create,
+    Update,
+    Skip,
+    /// The entity is no longer in the producer's data
+    Deprecate,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityKind {
+    Agency,
+    Route,
+    Level,
+    Stop,
+    RoutePattern,
+}
+
+/// All the kinds of entity, in the order of the import
+const ENTITY_KINDS: &[EntityKind] = &[
+    EntityKind::Agency,
+    EntityKind::Route,
+    EntityKind::Level,
+    EntityKind::Stop,
+    EntityKind::RoutePattern,
+];
+
+impl EntityKind {
+    fn name(self) -> &'static str {
+        match self {
+            EntityKind::Agency => "agencies",
+            EntityKind::Route => "routes",
+            EntityKind::Level => "levels",
+            EntityKind::Stop => "stops",
+            EntityKind::RoutePattern => "route patterns",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlannedEntity {
+    pub kind: EntityKind,
+    pub gtfs_id: String,
+    pub label: String,
+    pub action: Action,
+    /// Wikibase id of the entity.
+    /// In dry-run mode, the entities to create are given a placeholder id (`new:<kind>:<gtfs_id>`)
+    pub id: String,
+}
+
+/// A claim added to an already existing entity
+#[derive(Serialize, Debug)]
+pub struct PlannedLink {
+    pub entity: String,
+    pub property: String,
+    pub value: serde_json::Value,
+}
+
+/// Number of entities of a kind, by action
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EntityCounts {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub deprecated: usize,
+}
+
+impl EntityCounts {
+    /// number of entities of the GTFS (the deprecated ones are not in it anymore)
+    pub fn imported(&self) -> usize {
+        self.created + self.updated + self.skipped
+    }
+}
+
+/// An entity that could not be imported, in keep-going mode
+#[derive(Serialize, Debug)]
+pub struct FailedEntity {
+    pub kind: EntityKind,
+    pub gtfs_id: String,
+    pub error: String,
+}
+
+/// Duration of a phase of the import
+#[derive(Serialize, Debug)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub seconds: f64,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// The same file had already been imported, nothing has been done
+    pub already_imported: bool,
+    pub producer: String,
+    pub data_source: String,
+    pub entities: Vec<PlannedEntity>,
+    /// Number of entities of each kind, by action
+    pub counts: BTreeMap<EntityKind, EntityCounts>,
+    pub links: Vec<PlannedLink>,
+    /// Number of claims of the links actually added (none in dry-run mode)
+    pub relations_added: usize,
+    /// Anomalies of the data that did not stop the import,
+    /// like a relation with an entity that could not be found
+    pub warnings: Vec<String>,
+    /// Errors that did not stop the import
+    pub errors: Vec<String>,
+    /// Entities that could not be imported, their relations have not been added
+    pub failures: Vec<FailedEntity>,
+    pub timings: Vec<PhaseTiming>,
+}
+
+impl ImportReport {
+    pub fn add_entity(
+        &mut self,
+        kind: EntityKind,
+        gtfs_id: &str,
+        label: &str,
+        action: Action,
+        id: &str,
+    ) {
+        let counts = self.counts.entry(kind).or_default();
+        match action {
+            Action::Create => counts.created += 1,
+            Action::Update => counts.updated += 1,
+            Action::Skip => counts.skipped += 1,
+            Action::Deprecate => counts.deprecated += 1,
+        }
+        self.entities.push(PlannedEntity {
+            kind,
+            gtfs_id: gtfs_id.to_owned(),
+            label: label.to_owned(),
+            action,
+            id: id.to_owned(),
+        });
+    }
+
+    pub fn counts(&self, kind: EntityKind) -> EntityCounts {
+        self.counts.get(&kind).copied().unwrap_or_default()
+    }
+
+    /// log a warning and keep it in the report
+    pub fn warn(&mut self, message: String) {
+        log::warn!("{}", message);
+        self.warnings.push(message);
+    }
+
+    /// log an error that does not stop the import and keep it in the report
+    pub fn error(&mut self, message: String) {
+        log::error!("{}", message);
+        self.errors.push(message);
+    }
+
+    /// log the failure of the import of an entity and keep it in the report
+    pub fn add_failure(&mut self, kind: EntityKind, gtfs_id: &str, error: &anyhow::Error) {
+        log::error!("impossible to import {:?} {}: {:?}", kind, gtfs_id, error);
+        self.failures.push(FailedEntity {
+            kind,
+            gtfs_id: gtfs_id.to_owned(),
+            error: format!("{:#}", error),
+        });
+    }
+
+    /// record the duration of a phase started at `start`
+    pub fn add_timing(&mut self, phase: &str, start: Instant) {
+        let seconds = start.elapsed().as_secs_f64();
+        log::info!("phase {} done in {:.1}s", phase, seconds);
+        self.timings.push(PhaseTiming {
+            phase: phase.to_owned(),
+            seconds,
+        });
+    }
+
+    /// Short human readable description of the plan
+    pub fn summary(&self) -> String {
+        if self.already_imported {
+            return format!(
+                "The GTFS has already been imported for producer {} in data source {}, use --force to import it again",
+                self.producer, self.data_source
+            );
+        }
+        let mut lines = vec![format!(
+            "{} of producer {} with data source {}",
+            if self.dry_run {
+                "Import plan"
+            } else {
+                "Import"
+            },
+            self.producer,
+            self.data_source
+        )];
+        for kind in ENTITY_KINDS {
+            let counts = self.counts(*kind);
+            lines.push(format!(
+                "  {}: {} created, {} updated, {} skipped, {} deprecated",
+                kind.name(),
+                counts.created,
+                counts.updated,
+                counts.skipped,
+                counts.deprecated,
+            ));
+        }
+        if self.dry_run {
+            lines.push(format!("  links: {} claims planned", self.links.len()));
+        } else {
+            lines.push(format!("  links: {} claims added", self.relations_added));
+        }
+        if !self.warnings.is_empty() {
+            lines.push(format!("  {} warnings", self.warnings.len()));
+        }
+        if !self.errors.is_empty() {
+            lines.push(format!("  {} errors", self.errors.len()));
+        }
+        if !self.failures.is_empty() {
+            lines.push(format!(
+                "  {} entities could not be imported:",
+                self.failures.len()
+            ));
+            for failure in &self.failures {
+                lines.push(format!(
+                    "    {:?} {}: {}",
+                    failure.kind, failure.gtfs_id, failure.error
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+}
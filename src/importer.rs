@@ -1,157 +1,1191 @@
+use crate::checkpoint::{Checkpoint, Interrupted, Phase};
 use crate::clients::api_client;
-use crate::topo_query::TopoQuery;
-use crate::topo_writer::TopoWriter;
+use crate::gtfs_extra;
+use crate::import_report::{Action, EntityKind, ImportReport, PlannedLink};
+use crate::journal::{self, Journal};
+use crate::known_entities::EntitiesId;
+use crate::store::Store;
+use crate::topo_query::ExistingEntities;
+use crate::topo_writer::{route_label, ClaimsBatch, ImportStatus, TopoWriter};
 use anyhow::Context;
 use anyhow::Error;
 use log::info;
-use std::collections::{HashMap, HashSet};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Options of a GTFS import
+#[derive(Debug, Default, Clone)]
+pub struct ImportOptions {
+    /// Update the already existing entities
+    pub override_existing: bool,
+    /// Mark the routes and stops of the producer that are not in the GTFS anymore
+    pub deprecate_missing: bool,
+    /// Import the GTFS even if the same file has already been imported for the producer
+    pub force: bool,
+    /// Checkpoint file used to resume an interrupted import
+    pub checkpoint: Option<std::path::PathBuf>,
+}
+
+/// A parsed GTFS, with the files not handled by gtfs_structures
+struct Feed {
+    gtfs: gtfs_structures::Gtfs,
+    route_details: HashMap<String, gtfs_extra::RouteDetails>,
+    transfers: Vec<gtfs_extra::Transfer>,
+    levels: Vec<gtfs_extra::Level>,
+    stop_details: HashMap<String, gtfs_extra::StopDetails>,
+    pathways: Vec<gtfs_extra::Pathway>,
+    route_patterns: Vec<gtfs_extra::RoutePattern>,
+}
+
+impl Feed {
+    fn read(raw_gtfs: gtfs_structures::RawGtfs, gtfs_filename: &str) -> Result<Self, Error> {
+        let gtfs = gtfs_structures::Gtfs::try_from(raw_gtfs).map_err(|e| e.compat())?;
+        Ok(Self {
+            route_patterns: gtfs_extra::route_patterns(&gtfs.trips),
+            gtfs,
+            route_details: gtfs_extra::read_route_details(gtfs_filename)?
+                .into_iter()
+                .map(|d| (d.route_id.clone(), d))
+                .collect(),
+            transfers: gtfs_extra::read_transfers(gtfs_filename)?,
+            levels: gtfs_extra::read_levels(gtfs_filename)?,
+            stop_details: gtfs_extra::read_stop_details(gtfs_filename)?
+                .into_iter()
+                .map(|d| (d.stop_id.clone(), d))
+                .collect(),
+            pathways: gtfs_extra::read_pathways(gtfs_filename)?,
+        })
+    }
+}
+
+/// A failed import, with the report of what had been done before the failure
+pub struct ImportError {
+    pub error: Error,
+    pub report: ImportReport,
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.error, f)
+    }
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// What the phases of the import of a GTFS share
+struct ImportContext<'a> {
+    data_source_id: &'a str,
+    producer_id: &'a str,
+    producer_name: &'a str,
+    options: &'a ImportOptions,
+    checkpoint: Option<&'a Checkpoint>,
+    report: &'a mut ImportReport,
+}
+
+/// A route pattern whose route and stops have been imported
+struct PatternToImport<'a> {
+    pattern: &'a gtfs_extra::RoutePattern,
+    label: String,
+    route_id: &'a String,
+    stop_ids: Vec<&'a String>,
+}
+
+/// Result of the import of an entity
+struct Imported {
+    gtfs_id: String,
+    name: String,
+    action: Action,
+    id: String,
+}
+
+impl Imported {
+    fn new(gtfs_id: String, name: &str, action: Action, id: String) -> Self {
+        Self {
+            gtfs_id,
+            name: name.to_owned(),
+            action,
+            id,
+        }
+    }
+}
 
 pub struct GtfsImporter {
-    pub writer: TopoWriter,
-    pub query: TopoQuery,
+    pub store: Arc<dyn Store>,
+    /// Write side of the importer, there is none in dry-run mode
+    pub writer: Option<TopoWriter>,
+    /// Local journal of the created entities, checked before the query service
+    pub journal: Option<Journal>,
+    /// Record the entities that cannot be imported in the report and continue
+    /// with the others, instead of stopping the import at the first error
+    pub keep_going: bool,
+    /// Workers sending the requests, its size is the number of concurrent edits
+    pool: rayon::ThreadPool,
+}
+
+fn worker_pool(size: usize) -> Result<rayon::ThreadPool, Error> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(size)
+        .thread_name(|i| format!("import-worker-{}", i))
+        .build()
+        .context("impossible to create the worker pool")
 }
 
 impl GtfsImporter {
-    pub fn new(api_endpoint: &str, sparql_enpoint: &str, topo_id_id: &str) -> Result<Self, Error> {
-        let query = TopoQuery::new(sparql_enpoint, topo_id_id)
-            .context("impossible to create query client")?;
+    /// create an importer writing in the store
+    pub fn new(store: Arc<dyn Store>) -> Result<Self, Error> {
+        Ok(Self {
+            writer: Some(TopoWriter::new(store.clone())),
+            store,
+            journal: None,
+            keep_going: false,
+            pool: worker_pool(1)?,
+        })
+    }
+
+    /// create an importer that only reads the store
+    /// the imports are only planned, nothing is written
+    pub fn new_dry_run(store: Arc<dyn Store>) -> Result<Self, Error> {
         Ok(Self {
-            writer: TopoWriter::new(api_endpoint, query.known_entities.clone())?,
-            query,
+            writer: None,
+            store,
+            journal: None,
+            keep_going: false,
+            pool: worker_pool(1)?,
         })
     }
 
+    /// set the number of entities imported at the same time, 1 by default
+    pub fn set_concurrent_edits(&mut self, concurrent_edits: usize) -> Result<(), Error> {
+        self.pool = worker_pool(concurrent_edits)?;
+        Ok(())
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.writer.is_none()
+    }
+
+    /// record the creation of an entity in the journal
+    fn record_creation(
+        &self,
+        producer_id: &str,
+        entity_type: &str,
+        gtfs_id: &str,
+        id: &str,
+    ) -> Result<(), Error> {
+        match &self.journal {
+            Some(journal) => journal.record(producer_id, entity_type, gtfs_id, id),
+            None => Ok(()),
+        }
+    }
+
+    fn known_entities(&self) -> &EntitiesId {
+        self.store.known_entities()
+    }
+
+    /// Load all the routes, levels, stops and route patterns already imported for a producer
+    /// to be able to find them without a query for each entity
+    fn find_existing_entities(&self, producer_id: &str) -> Result<ExistingEntities, Error> {
+        let known_entities = self.known_entities();
+        let mut types = known_entities.stop_types();
+        types.push(&known_entities.items.route);
+        types.push(&known_entities.items.level);
+        types.push(&known_entities.items.route_pattern);
+        let entities = self.store.find_producer_entities(producer_id, &types)?;
+        log::info!(
+            "{} entities already exist for producer {}",
+            entities.len(),
+            producer_id
+        );
+        let mut existing = ExistingEntities::default();
+        for e in entities {
+            existing.add(&e.instance_of, &e.gtfs_id, &e.id);
+            if e.removed {
+                existing.add_removed(&e.id);
+            }
+        }
+        Ok(existing)
+    }
+
+    /// Import the GTFS for the producer.
+    /// If the import fails, the error is returned with the report of what has been done
     pub fn import_gtfs(
         &self,
         gtfs_filename: &str,
         producer_id: &str,
         producer_name: &str,
-        override_existing: bool,
+        options: &ImportOptions,
+    ) -> Result<ImportReport, ImportError> {
+        let mut report = ImportReport {
+            dry_run: self.is_dry_run(),
+            producer: producer_id.to_owned(),
+            ..Default::default()
+        };
+        match self.run_import(
+            gtfs_filename,
+            producer_id,
+            producer_name,
+            options,
+            &mut report,
+        ) {
+            Ok(()) => Ok(report),
+            Err(error) => Err(ImportError { error, report }),
+        }
+    }
+
+    fn run_import(
+        &self,
+        gtfs_filename: &str,
+        producer_id: &str,
+        producer_name: &str,
+        options: &ImportOptions,
+        report: &mut ImportReport,
     ) -> Result<(), anyhow::Error> {
         let raw_gtfs = gtfs_structures::RawGtfs::new(gtfs_filename).map_err(|e| e.compat())?;
 
         log::info!("import gtfs version {}", crate::GIT_VERSION);
-        let data_source_id =
-            self.writer
-                .insert_data_source(&raw_gtfs.sha256, &producer_id, gtfs_filename)?;
+        let sha_256 = match &raw_gtfs.sha256 {
+            Some(sha) => sha.clone(),
+            None => gtfs_extra::sha256(gtfs_filename)?,
+        };
+        let checkpoint = match &options.checkpoint {
+            Some(_) if self.is_dry_run() => {
+                log::warn!("the checkpoint is not used in dry-run mode");
+                None
+            }
+            Some(path) => Some(Checkpoint::open(path, producer_id, &sha_256)?),
+            None => None,
+        };
+        let resumed_data_source = checkpoint.as_ref().and_then(|c| c.data_source());
+        if !options.force && resumed_data_source.is_none() {
+            let journaled = self
+                .journal
+                .as_ref()
+                .and_then(|j| j.get(producer_id, journal::DATA_SOURCE, &sha_256));
+            let already_imported = match journaled {
+                Some(id) => Some(id),
+                None => self
+                    .store
+                    .find_data_source_by_sha256(producer_id, &sha_256)?,
+            };
+            if let Some(data_source_id) = already_imported {
+                log::info!(
+                    "the file {} has already been imported for producer {} in data source {}, nothing to do",
+                    gtfs_filename,
+                    producer_id,
+                    data_source_id
+                );
+                report.already_imported = true;
+                report.data_source = data_source_id;
+                return Ok(());
+            }
+        }
+        // the whole GTFS is read before writing anything,
+        // to avoid creating a data source for an invalid file
+        let read_start = Instant::now();
+        let feed = Feed::read(raw_gtfs, gtfs_filename)
+            .with_context(|| format!("invalid GTFS {}", gtfs_filename))?;
 
-        let gtfs = gtfs_structures::Gtfs::try_from(raw_gtfs).map_err(|e| e.compat())?;
+        let data_source_id = match (&self.writer, resumed_data_source) {
+            (Some(_), Some(id)) => {
+                log::info!("resuming the import in data source {}", id);
+                id
+            }
+            (Some(writer), None) => {
+                let id = writer.insert_data_source(
+                    &Some(sha_256.clone()),
+                    &producer_id,
+                    gtfs_filename,
+                )?;
+                if let Some(checkpoint) = &checkpoint {
+                    checkpoint.set_data_source(&id)?;
+                }
+                id
+            }
+            (None, _) => "new:data_source".to_owned(),
+        };
+        report.data_source = data_source_id.clone();
+        report.add_timing("read", read_start);
+
+        let result = self.import_feed(
+            &feed,
+            &mut ImportContext {
+                data_source_id: &data_source_id,
+                producer_id,
+                producer_name,
+                options,
+                checkpoint: checkpoint.as_ref(),
+                report: &mut *report,
+            },
+        );
+        // after a failure the checkpoint is kept, everything is already written in it
+        if let (Some(checkpoint), Ok(())) = (&checkpoint, &result) {
+            if let Err(e) = checkpoint.remove() {
+                report.error(format!("{:?}", e));
+            }
+        }
+        if let Some(writer) = &self.writer {
+            if result.is_ok() {
+                if let Err(e) = writer.set_import_summary(&data_source_id, report) {
+                    report.error(format!("{:?}", e));
+                }
+            }
+            let status = match (&result, &checkpoint) {
+                // some entities are missing
+                (Ok(()), _) if !report.failures.is_empty() => Some(ImportStatus::Failed),
+                (Ok(()), _) => Some(ImportStatus::Completed),
+                // the import will be resumed with the checkpoint
+                (Err(_), Some(_)) => None,
+                (Err(_), None) => Some(ImportStatus::Failed),
+            };
+            if let Some(status) = status {
+                if let Err(e) = writer.set_import_status(&data_source_id, status) {
+                    report.error(format!("{:?}", e));
+                }
+            }
+            // the file is considered as imported only once the import is completed
+            if status == Some(ImportStatus::Completed) {
+                if let Err(e) = self.record_creation(
+                    producer_id,
+                    journal::DATA_SOURCE,
+                    &sha_256,
+                    &data_source_id,
+                ) {
+                    report.error(format!("{:?}", e));
+                }
+            }
+        }
+        result.with_context(|| format!("import in data source {} failed", data_source_id))
+    }
 
-        let route_mapping =
-            self.import_routes(&gtfs.routes, &data_source_id, producer_id, producer_name)?;
-        let stop_mapping =
-            self.import_stops(&gtfs.stops, &data_source_id, producer_id, override_existing)?;
-        self.insert_stop_relations(&gtfs.stops, &stop_mapping)?;
-        self.insert_stop_route_relations(&gtfs.trips, &stop_mapping, &route_mapping)?;
+    /// import all the entities of the feed and their relations in the data source
+    fn import_feed(&self, feed: &Feed, ctx: &mut ImportContext) -> Result<(), Error> {
+        let Feed {
+            gtfs,
+            route_details,
+            transfers,
+            levels,
+            stop_details,
+            pathways,
+            route_patterns,
+        } = feed;
+        let ImportContext {
+            data_source_id,
+            producer_id,
+            options,
+            checkpoint,
+            ..
+        } = *ctx;
+        let mut existing = self.find_existing_entities(producer_id)?;
+        if let Some(journal) = &self.journal {
+            // the entities recently created might not be in the query service yet
+            for (entity_type, gtfs_id, id) in journal.producer_entries(producer_id) {
+                existing.add(&entity_type, &gtfs_id, &id);
+            }
+        }
+        let start = Instant::now();
+        let agency_mapping = self.import_agencies(&gtfs.agencies, ctx)?;
+        ctx.report.add_timing("agencies", start);
+        let start = Instant::now();
+        let route_mapping = self.import_routes(&gtfs.routes, route_details, &existing, ctx)?;
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.finish(Phase::Routes)?;
+        }
+        ctx.report.add_timing("routes", start);
+        let start = Instant::now();
+        let level_mapping = self.import_levels(levels, &existing, ctx)?;
+        ctx.report.add_timing("levels", start);
+        let start = Instant::now();
+        let stop_mapping = self.import_stops(&gtfs.stops, stop_details, &existing, ctx)?;
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.finish(Phase::Stops)?;
+        }
+        ctx.report.add_timing("stops", start);
+        let start = Instant::now();
+        let route_pattern_mapping = self.import_route_patterns(
+            route_patterns,
+            gtfs,
+            &route_mapping,
+            &stop_mapping,
+            &existing,
+            ctx,
+        )?;
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.finish(Phase::RoutePatterns)?;
+        }
+        ctx.report.add_timing("route_patterns", start);
+        let report = &mut *ctx.report;
+        let imported_ids: HashSet<_> = route_mapping
+            .values()
+            .chain(stop_mapping.values())
+            .chain(route_pattern_mapping.values())
+            .collect();
+        self.restore_reimported_entities(&imported_ids, &existing)?;
+
+        // the relations are gathered to make only one edit by entity
+        let mut claims = ClaimsBatch::default();
+        self.insert_stop_relations(&gtfs.stops, &stop_mapping, &mut claims, report);
+        self.insert_transfer_relations(transfers, &stop_mapping, &mut claims, report);
+        self.insert_stop_level_relations(
+            stop_details,
+            &stop_mapping,
+            &level_mapping,
+            &mut claims,
+            report,
+        );
+        self.insert_pathway_relations(pathways, &stop_mapping, &mut claims, report);
+        self.insert_seen_in_claims(report, data_source_id, &mut claims);
+        if options.deprecate_missing {
+            self.deprecate_missing_entities(
+                feed,
+                producer_id,
+                &imported_ids,
+                data_source_id,
+                report,
+                &mut claims,
+            )?;
+        }
+        self.insert_stop_route_relations(
+            &gtfs.trips,
+            &stop_mapping,
+            &route_mapping,
+            &mut claims,
+            report,
+        );
+        self.insert_route_agency_relations(
+            &gtfs.routes,
+            &route_mapping,
+            &agency_mapping,
+            &mut claims,
+            report,
+        );
+        self.remove_existing_claims(&mut claims, report)?;
+        report.links = claims
+            .iter()
+            .map(|(entity, claim)| PlannedLink {
+                entity: entity.to_owned(),
+                property: claim["mainsnak"]["property"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_owned(),
+                value: claim["mainsnak"]["datavalue"]["value"].clone(),
+            })
+            .collect();
+        let start = Instant::now();
+        self.add_relations(claims, checkpoint, report)?;
+        report.add_timing("relations", start);
 
         Ok(())
     }
 
-    pub fn import_routes(
+    /// Remove the relations already in the database from the batch,
+    /// so that importing a GTFS again does not duplicate them.
+    /// The entities created by this import have no relation yet, they are not fetched
+    fn remove_existing_claims(
         &self,
-        routes: &HashMap<String, gtfs_structures::Route>,
-        data_source_id: &str,
-        producer_id: &str,
-        producer_name: &str,
+        claims: &mut ClaimsBatch,
+        report: &ImportReport,
+    ) -> Result<(), Error> {
+        let created: HashSet<_> = report
+            .entities
+            .iter()
+            .filter(|e| e.action == Action::Create)
+            .map(|e| e.id.as_str())
+            .collect();
+        let existing_ids: Vec<_> = claims
+            .entities()
+            .filter(|id| !created.contains(id))
+            .map(|id| id.to_owned())
+            .collect();
+        let entities = self.pool.install(|| {
+            existing_ids
+                .par_iter()
+                .map(|id| self.store.get_entity(id))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        for entity in &entities {
+            claims.remove_existing(entity, &self.known_entities().properties.gtfs_id);
+        }
+        Ok(())
+    }
+
+    /// add the claims of the relations, with one edit by entity.
+    /// When resuming an import, the entities already edited are skipped
+    fn add_relations(
+        &self,
+        mut claims: ClaimsBatch,
+        checkpoint: Option<&Checkpoint>,
+        report: &mut ImportReport,
+    ) -> Result<(), Error> {
+        let writer = match &self.writer {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        match checkpoint {
+            Some(checkpoint) if checkpoint.is_finished(Phase::Relations) => {
+                info!("the relations have already been added, skipping");
+            }
+            Some(checkpoint) => {
+                claims.retain(|entity_id| !checkpoint.is_edited(entity_id));
+                let nb_claims = claims.iter().count();
+                writer.add_claims_batch(claims, &self.pool, |entity_id| {
+                    checkpoint.add_edited(entity_id)?;
+                    checkpoint.check_interrupted()
+                })?;
+                report.relations_added += nb_claims;
+                checkpoint.finish(Phase::Relations)?;
+            }
+            None => {
+                let nb_claims = claims.iter().count();
+                writer.add_claims_batch(claims, &self.pool, |_| Ok(()))?;
+                report.relations_added += nb_claims;
+            }
+        }
+        Ok(())
+    }
+
+    /// Import the entities with the worker pool.
+    /// The entities are added to the report in the order of `items`,
+    /// so the result does not depend on the scheduling of the workers
+    fn import_entities<T: Sync>(
+        &self,
+        kind: EntityKind,
+        items: &[T],
+        report: &mut ImportReport,
+        gtfs_id: impl Fn(&T) -> String,
+        import: impl Fn(&T) -> Result<Imported, Error> + Sync + Send,
+    ) -> Result<HashMap<String, String>, Error> {
+        let results: Vec<Result<Imported, Error>> = if self.keep_going {
+            self.pool
+                .install(|| items.par_iter().map(&import).collect())
+        } else {
+            // the import stops at the first error
+            self.pool
+                .install(|| items.par_iter().map(&import).collect::<Result<Vec<_>, _>>())?
+                .into_iter()
+                .map(Ok)
+                .collect()
+        };
+        let mut mapping = HashMap::new();
+        for (item, result) in items.iter().zip(results) {
+            match result {
+                Ok(i) => {
+                    report.add_entity(kind, &i.gtfs_id, &i.name, i.action, &i.id);
+                    mapping.insert(i.gtfs_id, i.id);
+                }
+                // an interruption stops the import, even in keep-going mode
+                Err(e) if e.is::<Interrupted>() => return Err(e),
+                // the failed entity is not in the mapping, so it gets no relation
+                Err(e) => report.add_failure(kind, &gtfs_id(item), &e),
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// import the agencies of the GTFS
+    /// the returned mapping is indexed by the agency_id (empty if the agency has no id)
+    fn import_agencies(
+        &self,
+        agencies: &[gtfs_structures::Agency],
+        ctx: &mut ImportContext,
     ) -> Result<std::collections::HashMap<String, String>, anyhow::Error> {
-        routes
-            .values()
-            .map(|route| {
-                let r = self.query.find_route(&producer_id, &route.id)?;
-                match r {
+        let ImportContext {
+            data_source_id,
+            producer_id,
+            checkpoint,
+            ..
+        } = *ctx;
+        let agency_type = &self.known_entities().items.agency;
+        let agency_key = |agency: &gtfs_structures::Agency| agency.id.clone().unwrap_or_default();
+        self.import_entities(
+            EntityKind::Agency,
+            agencies,
+            ctx.report,
+            agency_key,
+            |agency| {
+                let gtfs_id = agency.id.clone().unwrap_or_default();
+                // like in the query, the agency is identified by its name when it has no id
+                let key = agency.id.as_ref().unwrap_or(&agency.name);
+                if let Some(checkpoint) = checkpoint {
+                    checkpoint.check_interrupted()?;
+                    if let Some(id) = checkpoint.created(EntityKind::Agency, key) {
+                        // created before the interruption of the import
+                        return Ok(Imported::new(gtfs_id, &agency.name, Action::Create, id));
+                    }
+                }
+                let journaled = self
+                    .journal
+                    .as_ref()
+                    .and_then(|j| j.get(producer_id, agency_type, key));
+                let a = match journaled {
+                    Some(id) => Some(id),
+                    None => self.store.find_agency(&producer_id, &agency)?,
+                };
+                match a {
                     None => {
+                        info!("Agency “{}” does not exist, inserting", agency.name);
+                        let wikibase_id = match &self.writer {
+                            Some(writer) => {
+                                let id = writer.insert_agency(&agency, &data_source_id)?;
+                                self.record_creation(producer_id, agency_type, key, &id)?;
+                                if let Some(checkpoint) = checkpoint {
+                                    checkpoint.add_created(EntityKind::Agency, key, &id)?;
+                                }
+                                id
+                            }
+                            None => format!("new:agency:{}", agency.name),
+                        };
+                        Ok(Imported::new(
+                            gtfs_id,
+                            &agency.name,
+                            Action::Create,
+                            wikibase_id,
+                        ))
+                    }
+                    Some(agency_id) => {
                         info!(
-                            "Route “{}” ({}) does not exist, inserting",
-                            route.long_name, route.short_name
+                            "Agency “{}” already exists with id {}, skipping",
+                            agency.name, agency_id
                         );
-                        let wikibase_id =
-                            self.writer
-                                .insert_route(&route, &data_source_id, producer_name)?;
-                        Ok((route.id.to_owned(), wikibase_id))
+                        Ok(Imported::new(
+                            gtfs_id,
+                            &agency.name,
+                            Action::Skip,
+                            agency_id,
+                        ))
                     }
-                    Some(route_id) => {
+                }
+            },
+        )
+    }
+
+    fn import_routes(
+        &self,
+        routes: &HashMap<String, gtfs_structures::Route>,
+        route_details: &HashMap<String, gtfs_extra::RouteDetails>,
+        existing: &ExistingEntities,
+        ctx: &mut ImportContext,
+    ) -> Result<std::collections::HashMap<String, String>, anyhow::Error> {
+        let ImportContext {
+            data_source_id,
+            producer_id,
+            producer_name,
+            options,
+            checkpoint,
+            ..
+        } = *ctx;
+        let route_type = &self.known_entities().items.route;
+        let mut routes: Vec<_> = routes.values().collect();
+        routes.sort_by(|a, b| a.id.cmp(&b.id));
+        let route_id = |route: &&gtfs_structures::Route| route.id.clone();
+        self.import_entities(EntityKind::Route, &routes, ctx.report, route_id, |route| {
+            let label = route_label(route, producer_name);
+            if let Some(checkpoint) = checkpoint {
+                checkpoint.check_interrupted()?;
+                if let Some(id) = checkpoint.created(EntityKind::Route, &route.id) {
+                    // created before the interruption of the import
+                    return Ok(Imported::new(route.id.clone(), &label, Action::Create, id));
+                }
+            }
+            let r = existing.get(route_type, &route.id)?;
+            match r {
+                None => {
+                    info!(
+                        "Route “{}” ({}) does not exist, inserting",
+                        route.long_name, route.short_name
+                    );
+                    let wikibase_id = match &self.writer {
+                        Some(writer) => {
+                            let id = writer.insert_route(
+                                &route,
+                                route_details.get(&route.id),
+                                &data_source_id,
+                                producer_name,
+                            )?;
+                            self.record_creation(producer_id, route_type, &route.id, &id)?;
+                            if let Some(checkpoint) = checkpoint {
+                                checkpoint.add_created(EntityKind::Route, &route.id, &id)?;
+                            }
+                            id
+                        }
+                        None => format!("new:route:{}", route.id),
+                    };
+                    Ok(Imported::new(
+                        route.id.clone(),
+                        &label,
+                        Action::Create,
+                        wikibase_id,
+                    ))
+                }
+                Some(route_id) => {
+                    let action = if options.override_existing {
+                        info!(
+                            "Route “{}” ({}) already exists with id {}, updating it",
+                            route.long_name, route.short_name, route_id
+                        );
+                        if let Some(writer) = &self.writer {
+                            writer.update_route(
+                                &route_id,
+                                route,
+                                route_details.get(&route.id),
+                                producer_name,
+                            )?;
+                        }
+                        Action::Update
+                    } else {
                         info!(
                             "Route “{}” ({}) already exists with id {}, skipping",
                             route.long_name, route.short_name, route_id
                         );
-                        Ok((route.id.to_owned(), route_id.to_owned()))
-                    }
+                        Action::Skip
+                    };
+                    Ok(Imported::new(route.id.clone(), &label, action, route_id))
                 }
-            })
-            .collect()
+            }
+        })
+    }
+
+    fn import_levels(
+        &self,
+        levels: &[gtfs_extra::Level],
+        existing: &ExistingEntities,
+        ctx: &mut ImportContext,
+    ) -> Result<std::collections::HashMap<String, String>, anyhow::Error> {
+        let ImportContext {
+            data_source_id,
+            producer_id,
+            checkpoint,
+            ..
+        } = *ctx;
+        let level_type = &self.known_entities().items.level;
+        let level_id = |level: &gtfs_extra::Level| level.level_id.clone();
+        self.import_entities(EntityKind::Level, levels, ctx.report, level_id, |level| {
+            let name = level.level_name.as_deref().unwrap_or(&level.level_id);
+            if let Some(checkpoint) = checkpoint {
+                checkpoint.check_interrupted()?;
+                if let Some(id) = checkpoint.created(EntityKind::Level, &level.level_id) {
+                    // created before the interruption of the import
+                    return Ok(Imported::new(
+                        level.level_id.clone(),
+                        name,
+                        Action::Create,
+                        id,
+                    ));
+                }
+            }
+            let l = existing.get(level_type, &level.level_id)?;
+            match l {
+                None => {
+                    info!("Level “{}” does not exist, inserting", level.level_id);
+                    let wikibase_id = match &self.writer {
+                        Some(writer) => {
+                            let id = writer.insert_level(&level, &data_source_id)?;
+                            self.record_creation(producer_id, level_type, &level.level_id, &id)?;
+                            if let Some(checkpoint) = checkpoint {
+                                checkpoint.add_created(EntityKind::Level, &level.level_id, &id)?;
+                            }
+                            id
+                        }
+                        None => format!("new:level:{}", level.level_id),
+                    };
+                    Ok(Imported::new(
+                        level.level_id.clone(),
+                        name,
+                        Action::Create,
+                        wikibase_id,
+                    ))
+                }
+                Some(level_id) => {
+                    info!(
+                        "Level “{}” already exists with id {}, skipping",
+                        level.level_id, level_id
+                    );
+                    Ok(Imported::new(
+                        level.level_id.clone(),
+                        name,
+                        Action::Skip,
+                        level_id,
+                    ))
+                }
+            }
+        })
     }
 
-    pub fn import_stops(
+    fn import_stops(
         &self,
         stops: &HashMap<String, std::sync::Arc<gtfs_structures::Stop>>,
-        data_source_id: &str,
-        producer_id: &str,
-        override_existing: bool,
+        stop_details: &HashMap<String, gtfs_extra::StopDetails>,
+        existing: &ExistingEntities,
+        ctx: &mut ImportContext,
     ) -> Result<std::collections::HashMap<String, String>, anyhow::Error> {
-        stops
-            .values()
-            .map(|stop| {
-                let s = self.query.find_stop(&producer_id, &stop)?;
-                match s {
-                    None => {
+        let ImportContext {
+            data_source_id,
+            producer_id,
+            options,
+            checkpoint,
+            ..
+        } = *ctx;
+        let mut stops: Vec<_> = stops.values().collect();
+        stops.sort_by(|a, b| a.id.cmp(&b.id));
+        let stop_id = |stop: &&std::sync::Arc<gtfs_structures::Stop>| stop.id.clone();
+        self.import_entities(EntityKind::Stop, &stops, ctx.report, stop_id, |stop| {
+            if let Some(checkpoint) = checkpoint {
+                checkpoint.check_interrupted()?;
+                if let Some(id) = checkpoint.created(EntityKind::Stop, &stop.id) {
+                    // created before the interruption of the import
+                    return Ok(Imported::new(
+                        stop.id.clone(),
+                        &stop.name,
+                        Action::Create,
+                        id,
+                    ));
+                }
+            }
+            let stop_type = self.known_entities().location_type(stop);
+            let s = existing.get(stop_type, &stop.id)?;
+            match s {
+                None => {
+                    info!(
+                        "Stop “{}” ({}) does not exist, inserting",
+                        stop.name, stop.id
+                    );
+                    let wikibase_id = match &self.writer {
+                        Some(writer) => {
+                            let id = writer.insert_stop(
+                                &stop,
+                                stop_details.get(&stop.id),
+                                &data_source_id,
+                            )?;
+                            self.record_creation(producer_id, stop_type, &stop.id, &id)?;
+                            if let Some(checkpoint) = checkpoint {
+                                checkpoint.add_created(EntityKind::Stop, &stop.id, &id)?;
+                            }
+                            id
+                        }
+                        None => format!("new:stop:{}", stop.id),
+                    };
+                    Ok(Imported::new(
+                        stop.id.clone(),
+                        &stop.name,
+                        Action::Create,
+                        wikibase_id,
+                    ))
+                }
+                Some(stop_id) => {
+                    let action = if options.override_existing {
                         info!(
-                            "Stop “{}” ({}) does not exist, inserting",
-                            stop.name, stop.id
-                        );
-                        let wikibase_id = self.writer.insert_stop(&stop, &data_source_id)?;
-                        Ok((stop.id.to_owned(), wikibase_id))
-                    }
-                    Some(stop_id) => {
-                        if override_existing {
-                            info!(
                             "Stop “{}” ({}) already exists with id {}, updating it with new claims",
                             stop.name, stop.id, stop_id
                         );
-                            self.writer.update_stop(&stop_id, stop, data_source_id)?;
-                        } else {
-                            info!(
-                                "Stop “{}” ({}) already exists with id {}, skipping",
-                                stop.name, stop.id, stop_id
-                            );
+                        if let Some(writer) = &self.writer {
+                            writer.update_stop(&stop_id, stop, stop_details.get(&stop.id))?;
                         }
-                        Ok((stop.id.to_owned(), stop_id.to_owned()))
+                        Action::Update
+                    } else {
+                        info!(
+                            "Stop “{}” ({}) already exists with id {}, skipping",
+                            stop.name, stop.id, stop_id
+                        );
+                        Action::Skip
+                    };
+                    Ok(Imported::new(stop.id.clone(), &stop.name, action, stop_id))
+                }
+            }
+        })
+    }
+
+    /// import the distinct ordered stop sequences of the routes.
+    /// The patterns of the routes or stops that could not be imported are skipped
+    fn import_route_patterns(
+        &self,
+        patterns: &[gtfs_extra::RoutePattern],
+        gtfs: &gtfs_structures::Gtfs,
+        route_mapping: &std::collections::HashMap<String, String>,
+        stop_mapping: &std::collections::HashMap<String, String>,
+        existing: &ExistingEntities,
+        ctx: &mut ImportContext,
+    ) -> Result<std::collections::HashMap<String, String>, anyhow::Error> {
+        let ImportContext {
+            data_source_id,
+            producer_id,
+            producer_name,
+            checkpoint,
+            ..
+        } = *ctx;
+        let pattern_type = &self.known_entities().items.route_pattern;
+        let stop_name = |stop_id: &String| {
+            gtfs.stops
+                .get(stop_id)
+                .map(|s| s.name.clone())
+                .unwrap_or_else(|| stop_id.clone())
+        };
+        let mut to_import = Vec::new();
+        for pattern in patterns {
+            let (route, route_id) = match (
+                gtfs.routes.get(&pattern.route_id),
+                route_mapping.get(&pattern.route_id),
+            ) {
+                (Some(route), Some(route_id)) => (route, route_id),
+                _ => {
+                    ctx.report.warn(format!(
+                        "Could not find wikibase id for gtfs route id: {}",
+                        pattern.route_id
+                    ));
+                    continue;
+                }
+            };
+            let stop_ids: Option<Vec<_>> = pattern
+                .stop_ids
+                .iter()
+                .map(|stop_id| stop_mapping.get(stop_id))
+                .collect();
+            let stop_ids = match stop_ids {
+                Some(ids) => ids,
+                None => {
+                    ctx.report.warn(format!(
+                        "Could not find wikibase id for all the stops of route pattern {}",
+                        pattern.gtfs_id()
+                    ));
+                    continue;
+                }
+            };
+            // the patterns are never empty
+            let label = format!(
+                "{}: {} → {}",
+                route_label(route, producer_name),
+                stop_name(&pattern.stop_ids[0]),
+                stop_name(&pattern.stop_ids[pattern.stop_ids.len() - 1])
+            );
+            to_import.push(PatternToImport {
+                pattern,
+                label,
+                route_id,
+                stop_ids,
+            });
+        }
+        self.import_entities(
+            EntityKind::RoutePattern,
+            &to_import,
+            ctx.report,
+            |p| p.pattern.gtfs_id(),
+            |p| {
+                let gtfs_id = p.pattern.gtfs_id();
+                if let Some(checkpoint) = checkpoint {
+                    checkpoint.check_interrupted()?;
+                    if let Some(id) = checkpoint.created(EntityKind::RoutePattern, &gtfs_id) {
+                        // created before the interruption of the import
+                        return Ok(Imported::new(gtfs_id, &p.label, Action::Create, id));
                     }
                 }
-            })
-            .collect()
+                match existing.get(pattern_type, &gtfs_id)? {
+                    None => {
+                        info!(
+                            "Route pattern “{}” ({}) does not exist, inserting",
+                            p.label, gtfs_id
+                        );
+                        let wikibase_id = match &self.writer {
+                            Some(writer) => {
+                                let id = writer.insert_route_pattern(
+                                    p.pattern,
+                                    &p.label,
+                                    p.route_id,
+                                    &p.stop_ids,
+                                    data_source_id,
+                                )?;
+                                self.record_creation(producer_id, pattern_type, &gtfs_id, &id)?;
+                                if let Some(checkpoint) = checkpoint {
+                                    checkpoint.add_created(
+                                        EntityKind::RoutePattern,
+                                        &gtfs_id,
+                                        &id,
+                                    )?;
+                                }
+                                id
+                            }
+                            None => format!("new:route_pattern:{}", gtfs_id),
+                        };
+                        Ok(Imported::new(
+                            gtfs_id,
+                            &p.label,
+                            Action::Create,
+                            wikibase_id,
+                        ))
+                    }
+                    Some(pattern_id) => {
+                        info!(
+                            "Route pattern “{}” ({}) already exists with id {}, skipping",
+                            p.label, gtfs_id, pattern_id
+                        );
+                        Ok(Imported::new(gtfs_id, &p.label, Action::Skip, pattern_id))
+                    }
+                }
+            },
+        )
+    }
+
+    /// The entities already in the database are marked as seen in the new data source
+    /// (the new entities get this claim on creation)
+    pub fn insert_seen_in_claims(
+        &self,
+        report: &ImportReport,
+        data_source_id: &str,
+        claims: &mut ClaimsBatch,
+    ) {
+        for entity in report
+            .entities
+            .iter()
+            .filter(|e| e.action == Action::Skip || e.action == Action::Update)
+        {
+            claims.add(
+                &entity.id,
+                api_client::claim_item(&self.known_entities().properties.seen_in, data_source_id),
+            );
+        }
+    }
+
+    /// The entities marked as no longer present that are in the imported GTFS again
+    /// are not deprecated anymore
+    fn restore_reimported_entities(
+        &self,
+        imported_ids: &HashSet<&String>,
+        existing: &ExistingEntities,
+    ) -> Result<(), anyhow::Error> {
+        let property = &self.known_entities().properties.no_longer_present_since;
+        let mut restored: Vec<_> = imported_ids
+            .iter()
+            .filter(|id| existing.is_removed(id))
+            .collect();
+        restored.sort();
+        for id in restored {
+            info!(
+                "{} is in the producer's data again, removing its deprecation",
+                id
+            );
+            if let Some(writer) = &self.writer {
+                let entity = writer.store.get_entity(id)?;
+                let claims = entity
+                    .properties
+                    .get(property)
+                    .into_iter()
+                    .flatten()
+                    .map(|value| value.to_claim(property))
+                    .collect();
+                writer.store.remove_claims(id, claims)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark the routes, stops and route patterns linked to the producer's previous data sources
+    /// that are not in the imported GTFS as no longer present.
+    /// The entities of the GTFS that could not be imported (with `keep_going`) are not deprecated
+    fn deprecate_missing_entities(
+        &self,
+        feed: &Feed,
+        producer_id: &str,
+        imported_ids: &HashSet<&String>,
+        data_source_id: &str,
+        report: &mut ImportReport,
+        claims: &mut ClaimsBatch,
+    ) -> Result<(), anyhow::Error> {
+        let known_entities = &self.known_entities();
+        let mut types = known_entities.stop_types();
+        types.push(&known_entities.items.route);
+        types.push(&known_entities.items.route_pattern);
+        let failed: HashSet<_> = report
+            .failures
+            .iter()
+            .map(|f| (f.kind, f.gtfs_id.clone()))
+            .collect();
+        // the patterns of the failed routes and stops are not imported, but they are still in the GTFS
+        let patterns: HashSet<_> = feed.route_patterns.iter().map(|p| p.gtfs_id()).collect();
+        let entities = self.store.find_producer_entities(producer_id, &types)?;
+        for entity in entities
+            .iter()
+            .filter(|e| !e.removed && !imported_ids.contains(&e.id))
+        {
+            let kind = if entity.instance_of == known_entities.items.route {
+                EntityKind::Route
+            } else if entity.instance_of == known_entities.items.route_pattern {
+                EntityKind::RoutePattern
+            } else {
+                EntityKind::Stop
+            };
+            if failed.contains(&(kind, entity.gtfs_id.clone()))
+                || (kind == EntityKind::RoutePattern && patterns.contains(&entity.gtfs_id))
+            {
+                continue;
+            }
+            info!(
+                "“{}” ({}) is no longer in the producer's data, deprecating it",
+                entity.label, entity.gtfs_id
+            );
+            report.add_entity(
+                kind,
+                &entity.gtfs_id,
+                &entity.label,
+                Action::Deprecate,
+                &entity.id,
+            );
+            claims.add(
+                &entity.id,
+                api_client::claim_item(
+                    &known_entities.properties.no_longer_present_since,
+                    data_source_id,
+                ),
+            );
+        }
+        Ok(())
     }
 
     pub fn insert_stop_relations(
         &self,
         stops: &HashMap<String, std::sync::Arc<gtfs_structures::Stop>>,
         id_mapping: &std::collections::HashMap<String, String>,
-    ) -> Result<(), anyhow::Error> {
-        for stop in stops.values() {
+        claims: &mut ClaimsBatch,
+        report: &mut ImportReport,
+    ) {
+        // sorted to always add the claims and the warnings in the same order
+        let mut stops: Vec<_> = stops.values().collect();
+        stops.sort_by(|a, b| a.id.cmp(&b.id));
+        for stop in stops {
             if let Some(parent_gtfs_id) = &stop.parent_station {
                 let parent_wikibase_id = match id_mapping.get(parent_gtfs_id) {
                     Some(id) => id,
                     None => {
-                        log::warn!("Could not find wikibase id for gtfs id: {}", parent_gtfs_id);
+                        report.warn(format!(
+                            "Could not find wikibase id for gtfs id: {}",
+                            parent_gtfs_id
+                        ));
                         continue;
                     }
                 };
                 let child_wikibase_id = match id_mapping.get(&stop.id) {
                     Some(id) => id,
                     None => {
-                        log::warn!("Could not find wikibase id for gtfs id: {}", parent_gtfs_id);
+                        report.warn(format!(
+                            "Could not find wikibase id for gtfs id: {}",
+                            stop.id
+                        ));
                         continue;
                     }
                 };
-                let claim = api_client::claim_item(
-                    &self.query.known_entities.properties.part_of,
-                    parent_wikibase_id,
+                claims.add(
+                    child_wikibase_id,
+                    api_client::claim_item(
+                        &self.known_entities().properties.part_of,
+                        parent_wikibase_id,
+                    ),
                 );
-                self.writer
-                    .client
-                    .add_claims(child_wikibase_id, vec![claim])?;
             }
         }
-        Ok(())
     }
 
     pub fn insert_stop_route_relations(
@@ -159,13 +1193,16 @@ impl GtfsImporter {
         trips: &HashMap<String, gtfs_structures::Trip>,
         stop_mapping: &std::collections::HashMap<String, String>,
         route_mapping: &std::collections::HashMap<String, String>,
-    ) -> Result<(), anyhow::Error> {
-        log::info!("inserting stop/routes relations");
-        let mut stops_by_routes: HashMap<String, HashSet<String>> = HashMap::new();
+        claims: &mut ClaimsBatch,
+        report: &mut ImportReport,
+    ) {
+        log::info!("computing stop/routes relations");
+        // sorted to always add the claims and the warnings in the same order
+        let mut stops_by_routes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
         for trip in trips.values() {
             let stops = stops_by_routes
                 .entry(trip.route_id.clone())
-                .or_insert_with(HashSet::new);
+                .or_insert_with(BTreeSet::new);
             for s in &trip.stop_times {
                 stops.insert(s.stop.id.clone());
             }
@@ -174,7 +1211,10 @@ impl GtfsImporter {
             let route_topo_id = match route_mapping.get(route_id) {
                 Some(id) => id,
                 None => {
-                    log::warn!("Could not find wikibase id for gtfs route id: {}", route_id);
+                    report.warn(format!(
+                        "Could not find wikibase id for gtfs route id: {}",
+                        route_id
+                    ));
                     continue;
                 }
             };
@@ -182,19 +1222,218 @@ impl GtfsImporter {
                 let stop_topo_id = match stop_mapping.get(stop_id) {
                     Some(id) => id,
                     None => {
-                        log::warn!("Could not find wikibase id for gtfs id: {}", stop_id);
+                        report.warn(format!(
+                            "Could not find wikibase id for gtfs id: {}",
+                            stop_id
+                        ));
                         continue;
                     }
                 };
-                let claim = api_client::claim_item(
-                    &self.query.known_entities.properties.part_of,
-                    &route_topo_id,
+                claims.add(
+                    stop_topo_id,
+                    api_client::claim_item(
+                        &self.known_entities().properties.part_of,
+                        &route_topo_id,
+                    ),
                 );
+            }
+        }
+    }
 
-                self.writer.client.add_claims(stop_topo_id, vec![claim])?;
+    pub fn insert_route_agency_relations(
+        &self,
+        routes: &HashMap<String, gtfs_structures::Route>,
+        route_mapping: &std::collections::HashMap<String, String>,
+        agency_mapping: &std::collections::HashMap<String, String>,
+        claims: &mut ClaimsBatch,
+        report: &mut ImportReport,
+    ) {
+        let mut routes: Vec<_> = routes.values().collect();
+        routes.sort_by(|a, b| a.id.cmp(&b.id));
+        for route in routes {
+            // the agency_id of a route is optional if there is only one agency
+            let agency_topo_id = match &route.agency_id {
+                Some(agency_id) => agency_mapping.get(agency_id),
+                None if agency_mapping.len() == 1 => agency_mapping.values().next(),
+                None => None,
+            };
+            let agency_topo_id = match agency_topo_id {
+                Some(id) => id,
+                None => {
+                    report.warn(format!(
+                        "Could not find the agency of gtfs route id: {}",
+                        route.id
+                    ));
+                    continue;
+                }
+            };
+            let route_topo_id = match route_mapping.get(&route.id) {
+                Some(id) => id,
+                None => {
+                    report.warn(format!(
+                        "Could not find wikibase id for gtfs route id: {}",
+                        route.id
+                    ));
+                    continue;
+                }
+            };
+            claims.add(
+                route_topo_id,
+                api_client::claim_item(
+                    &self.known_entities().properties.operated_by,
+                    agency_topo_id,
+                ),
+            );
+        }
+    }
+
+    pub fn insert_transfer_relations(
+        &self,
+        transfers: &[gtfs_extra::Transfer],
+        stop_mapping: &std::collections::HashMap<String, String>,
+        claims: &mut ClaimsBatch,
+        report: &mut ImportReport,
+    ) {
+        let properties = &self.known_entities().properties;
+        for transfer in transfers {
+            // a transfer of type 3 means that the transfer is not possible
+            if transfer.transfer_type == Some(3) {
+                continue;
             }
+            let from_topo_id = match stop_mapping.get(&transfer.from_stop_id) {
+                Some(id) => id,
+                None => {
+                    report.warn(format!(
+                        "Could not find wikibase id for gtfs id: {}",
+                        transfer.from_stop_id
+                    ));
+                    continue;
+                }
+            };
+            let to_topo_id = match stop_mapping.get(&transfer.to_stop_id) {
+                Some(id) => id,
+                None => {
+                    report.warn(format!(
+                        "Could not find wikibase id for gtfs id: {}",
+                        transfer.to_stop_id
+                    ));
+                    continue;
+                }
+            };
+            let qualifiers = vec![
+                // the transfer type is omitted when it is empty or invalid
+                transfer.transfer_type.and_then(|t| {
+                    api_client::claim_string(&properties.gtfs_transfer_type, &t.to_string())
+                }),
+                transfer.min_transfer_time.and_then(|t| {
+                    api_client::claim_quantity(&properties.min_transfer_time, f64::from(t))
+                }),
+            ];
+            claims.add(
+                from_topo_id,
+                api_client::with_qualifiers(
+                    api_client::claim_item(&properties.transfer_to, to_topo_id),
+                    qualifiers,
+                ),
+            );
         }
+    }
 
-        Ok(())
+    pub fn insert_stop_level_relations(
+        &self,
+        stop_details: &HashMap<String, gtfs_extra::StopDetails>,
+        stop_mapping: &std::collections::HashMap<String, String>,
+        level_mapping: &std::collections::HashMap<String, String>,
+        claims: &mut ClaimsBatch,
+        report: &mut ImportReport,
+    ) {
+        let mut stop_details: Vec<_> = stop_details.iter().collect();
+        stop_details.sort_by(|a, b| a.0.cmp(b.0));
+        for (_, stop_level) in stop_details {
+            let level_id = match &stop_level.level_id {
+                Some(id) => id,
+                None => continue,
+            };
+            let level_topo_id = match level_mapping.get(level_id) {
+                Some(id) => id,
+                None => {
+                    report.warn(format!(
+                        "Could not find wikibase id for gtfs level id: {}",
+                        level_id
+                    ));
+                    continue;
+                }
+            };
+            let stop_topo_id = match stop_mapping.get(&stop_level.stop_id) {
+                Some(id) => id,
+                None => {
+                    report.warn(format!(
+                        "Could not find wikibase id for gtfs id: {}",
+                        stop_level.stop_id
+                    ));
+                    continue;
+                }
+            };
+            claims.add(
+                stop_topo_id,
+                api_client::claim_item(&self.known_entities().properties.on_level, level_topo_id),
+            );
+        }
+    }
+
+    pub fn insert_pathway_relations(
+        &self,
+        pathways: &[gtfs_extra::Pathway],
+        stop_mapping: &std::collections::HashMap<String, String>,
+        claims: &mut ClaimsBatch,
+        report: &mut ImportReport,
+    ) {
+        let properties = &self.known_entities().properties;
+        for pathway in pathways {
+            let from_topo_id = match stop_mapping.get(&pathway.from_stop_id) {
+                Some(id) => id,
+                None => {
+                    report.warn(format!(
+                        "Could not find wikibase id for gtfs id: {}",
+                        pathway.from_stop_id
+                    ));
+                    continue;
+                }
+            };
+            let to_topo_id = match stop_mapping.get(&pathway.to_stop_id) {
+                Some(id) => id,
+                None => {
+                    report.warn(format!(
+                        "Could not find wikibase id for gtfs id: {}",
+                        pathway.to_stop_id
+                    ));
+                    continue;
+                }
+            };
+            let qualifiers = vec![
+                api_client::claim_string(&properties.gtfs_id, &pathway.pathway_id),
+                api_client::claim_string(
+                    &properties.gtfs_pathway_mode,
+                    &pathway.pathway_mode.to_string(),
+                ),
+                api_client::claim_string(
+                    &properties.gtfs_is_bidirectional,
+                    &pathway.is_bidirectional.to_string(),
+                ),
+                pathway
+                    .length
+                    .and_then(|l| api_client::claim_quantity(&properties.length, l)),
+                pathway.traversal_time.and_then(|t| {
+                    api_client::claim_quantity(&properties.traversal_time, f64::from(t))
+                }),
+            ];
+            claims.add(
+                from_topo_id,
+                api_client::with_qualifiers(
+                    api_client::claim_item(&properties.pathway_to, to_topo_id),
+                    qualifiers,
+                ),
+            );
+        }
     }
 }
@@ -0,0 +1,108 @@
+//! Local journal of the entities created by the imports
+//!
+//! The query service is updated asynchronously, so an entity is not found by a sparql query
+//! right after its creation. The journal keeps the ids of the created entities
+//! so that an import following closely another one (or resuming after a crash)
+//! does not create duplicates.
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Key of the data sources in the journal, they are identified by the sha256 of their file
+pub const DATA_SOURCE: &str = "data_source";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    producer: String,
+    /// id of the type of the entity (route, stop_point, ...)
+    entity_type: String,
+    gtfs_id: String,
+    id: String,
+}
+
+/// The journal is a file with one json entry by line, new entries are appended to it
+pub struct Journal {
+    file: Mutex<std::fs::File>,
+    /// wikibase id by (producer, type, gtfs id)
+    ids: Mutex<HashMap<(String, String, String), String>>,
+}
+
+impl Journal {
+    /// open the journal, the file is created if it does not exist
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        let mut ids = HashMap::new();
+        if path.exists() {
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("impossible to open journal {:?}", path))?;
+            for line in std::io::BufReader::new(file).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let entry: Entry = serde_json::from_str(&line)
+                    .with_context(|| format!("invalid line in journal {:?}: {}", path, line))?;
+                ids.insert((entry.producer, entry.entity_type, entry.gtfs_id), entry.id);
+            }
+        }
+        log::info!("{} entities in the journal {:?}", ids.len(), path);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("impossible to open journal {:?}", path))?;
+        Ok(Self {
+            file: Mutex::new(file),
+            ids: Mutex::new(ids),
+        })
+    }
+
+    pub fn get(&self, producer: &str, entity_type: &str, gtfs_id: &str) -> Option<String> {
+        self.ids
+            .lock()
+            .unwrap()
+            .get(&(
+                producer.to_owned(),
+                entity_type.to_owned(),
+                gtfs_id.to_owned(),
+            ))
+            .cloned()
+    }
+
+    /// all the (type, gtfs id, wikibase id) of the producer
+    pub fn producer_entries(&self, producer: &str) -> Vec<(String, String, String)> {
+        self.ids
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((p, _, _), _)| p == producer)
+            .map(|((_, t, gtfs_id), id)| (t.clone(), gtfs_id.clone(), id.clone()))
+            .collect()
+    }
+
+    /// record the creation of an entity, the entry is written to the file immediately
+    pub fn record(
+        &self,
+        producer: &str,
+        entity_type: &str,
+        gtfs_id: &str,
+        id: &str,
+    ) -> Result<(), anyhow::Error> {
+        let entry = Entry {
+            producer: producer.to_owned(),
+            entity_type: entity_type.to_owned(),
+            gtfs_id: gtfs_id.to_owned(),
+            id: id.to_owned(),
+        };
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        file.flush()?;
+        self.ids
+            .lock()
+            .unwrap()
+            .insert((entry.producer, entry.entity_type, entry.gtfs_id), entry.id);
+        Ok(())
+    }
+}
@@ -1,4 +1,35 @@
+use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+lazy_static::lazy_static! {
+    // a reference to a known property or item, like `@instance_of`
+    static ref REFERENCE_REGEX: Regex = Regex::new(r"@(\w+)").unwrap();
+}
+
+// convert the Json representation of a simple struct (either Properties or Items)
+// into a hashmap field => value
+// it has lots of `expect`, because it should never fail, as it depends on the code (so checked at build time)
+fn as_map(val: serde_json::Value) -> HashMap<String, String> {
+    val.as_object()
+        .expect("invalid value")
+        .iter()
+        .map(|(k, v)| (k.clone(), v.as_str().expect("value not string").to_owned()))
+        .collect()
+}
+
+/// Replace the `@name` references by the value of `name` in the map,
+/// the unknown references are kept
+fn replace_references(text: &str, values: &HashMap<String, String>) -> String {
+    REFERENCE_REGEX
+        .replace_all(text, |captures: &Captures| {
+            values
+                .get(&captures[1])
+                .cloned()
+                .unwrap_or_else(|| captures[0].to_owned())
+        })
+        .into_owned()
+}
 
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct EntitiesId {
@@ -21,17 +52,34 @@ pub struct Properties {
     pub gtfs_short_name: String,
     /// Long name in the GTFS
     pub gtfs_long_name: String,
+    /// Description in the GTFS
+    pub gtfs_description: String,
     /// ID in the GTFS
     pub gtfs_id: String,
     /// Link to the Datasource
     pub data_source: String,
+    /// Link to the first Datasource in which an entity has been found
     pub first_seen_in: String,
+    /// Link to all the Datasources in which an entity has been found
+    pub seen_in: String,
+    /// Link to the first Datasource of the producer in which an entity was missing
+    pub no_longer_present_since: String,
     /// Path of the file used to import the data
     pub source: String,
     /// Format of the file used to import the data
     pub file_format: String,
     /// sha256 of the file used to import the data
     pub sha_256: String,
+    /// Status of the import of a Datasource (`completed` or `failed`)
+    pub import_status: String,
+    /// Number of routes in the GTFS of a Datasource
+    pub route_count: String,
+    /// Number of stops in the GTFS of a Datasource
+    pub stop_count: String,
+    /// Number of relations (claims between entities) added by the import of a Datasource
+    pub relation_count: String,
+    /// Number of warnings raised by the import of a Datasource
+    pub warning_count: String,
     /// Link to the Physical mode of the entity
     pub has_physical_mode: String,
     /// version of the tool used to import the entities
@@ -42,6 +90,58 @@ pub struct Properties {
     pub connecting_line: String,
     /// The coordinate of an entity
     pub coordinate_location: String,
+    /// Link from a route to the agency operating it
+    pub operated_by: String,
+    /// Url of an entity (like the website of an agency)
+    pub url: String,
+    /// Timezone of an entity
+    pub timezone: String,
+    /// Phone number of an entity
+    pub phone: String,
+    /// Shows that a transfer is possible from a stop to another one
+    pub transfer_to: String,
+    /// Type of a transfer (qualifier of `transfer_to`), with the GTFS value
+    pub gtfs_transfer_type: String,
+    /// Minimum time in seconds needed for a transfer (qualifier of `transfer_to`)
+    pub min_transfer_time: String,
+    /// Link from a stop to the level it is on
+    pub on_level: String,
+    /// Index of a level, 0 being the ground level
+    pub level_index: String,
+    /// Shows that a pathway exists from a stop to another one
+    pub pathway_to: String,
+    /// Mode of a pathway (qualifier of `pathway_to`), with the GTFS value
+    pub gtfs_pathway_mode: String,
+    /// If the pathway can be used in both directions (qualifier of `pathway_to`), with the GTFS value
+    pub gtfs_is_bidirectional: String,
+    /// Length in meters (qualifier of `pathway_to`)
+    pub length: String,
+    /// Time in seconds needed to walk through a pathway (qualifier of `pathway_to`)
+    pub traversal_time: String,
+    /// Color of a route, as an hexadecimal value (like `FFFFFF`)
+    pub color: String,
+    /// Color of the text to draw on the color of a route
+    pub text_color: String,
+    /// Order in which the routes should be presented
+    pub sort_order: String,
+    /// Short code of a stop in the GTFS, often displayed to the travelers
+    pub gtfs_code: String,
+    /// Code of the platform of a stop (like `G` or `3`)
+    pub platform_code: String,
+    /// Fare zone of a stop in the GTFS
+    pub gtfs_zone_id: String,
+    /// Link to the accessibility of a stop for wheelchairs
+    pub wheelchair_boarding: String,
+    /// Link from a route pattern to its route
+    pub pattern_of: String,
+    /// Direction of the trips of a route pattern in the GTFS (`0` or `1`)
+    pub gtfs_direction_id: String,
+    /// Headsign of the trips of a route pattern in the GTFS
+    pub gtfs_headsign: String,
+    /// Link from a route pattern to a stop it serves, qualified by `stop_sequence`
+    pub has_stop: String,
+    /// Position of a stop in a route pattern, starting at 1 (qualifier of `has_stop`)
+    pub stop_sequence: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -62,6 +162,13 @@ pub struct Items {
     pub stop_entrance: String,
     pub stop_boarding_area: String,
     pub stop_generic_node: String,
+    pub agency: String,
+    pub level: String,
+    pub route_pattern: String,
+    pub wheelchair_accessibility: String,
+    pub unknown_accessibility: String,
+    pub accessible: String,
+    pub not_accessible: String,
 }
 
 impl EntitiesId {
@@ -80,6 +187,51 @@ impl EntitiesId {
         }
     }
 
+    /// Item corresponding to the `wheelchair_boarding` value of a GTFS stop
+    pub fn wheelchair_boarding(&self, value: u8) -> Option<&str> {
+        match value {
+            0 => Some(&self.items.unknown_accessibility),
+            1 => Some(&self.items.accessible),
+            2 => Some(&self.items.not_accessible),
+            _ => None,
+        }
+    }
+
+    /// Replace in the claims the known properties and the known items,
+    /// given as `@field` (the field of `Properties` or `Items`).
+    /// For example to add a claim saying that an entity is an `instance of` `producer`:
+    /// `@instance_of=@producer`
+    pub fn replace_known_entities(&self, claims: Vec<(String, String)>) -> Vec<(String, String)> {
+        let properties =
+            as_map(serde_json::to_value(&self.properties).expect("impossible to serialize prop"));
+        let items =
+            as_map(serde_json::to_value(&self.items).expect("impossible to serialize items"))
+                .into_iter()
+                .map(|(k, v)| (k, format!("wd:{}", v)))
+                .collect();
+
+        claims
+            .into_iter()
+            .map(|(claim_prop, claim_value)| {
+                (
+                    replace_references(&claim_prop, &properties),
+                    replace_references(&claim_value, &items),
+                )
+            })
+            .collect()
+    }
+
+    /// All the possible types of a stop
+    pub fn stop_types(&self) -> Vec<&str> {
+        vec![
+            self.items.stop_point.as_str(),
+            self.items.stop_area.as_str(),
+            self.items.stop_entrance.as_str(),
+            self.items.stop_generic_node.as_str(),
+            self.items.stop_boarding_area.as_str(),
+        ]
+    }
+
     pub fn location_type(&self, stop: &gtfs_structures::Stop) -> &str {
         use gtfs_structures::LocationType::*;
         match stop.location_type {
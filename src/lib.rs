@@ -1,9 +1,14 @@
+pub mod checkpoint;
 pub mod clients;
 pub mod database_initializer;
 pub mod entity;
+pub mod gtfs_extra;
+pub mod import_report;
 pub mod importer;
+pub mod journal;
 pub mod known_entities;
 pub mod log;
+pub mod store;
 pub mod topo_query;
 pub mod topo_writer;
 
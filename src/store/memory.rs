@@ -0,0 +1,355 @@
+use super::Store;
+use crate::clients::api_client::claim_string;
+use crate::clients::{ApiError, ObjectType, PropertyDataType};
+use crate::entity::{claim_qualifiers, Entity, PropertyValue};
+use crate::known_entities::{EntitiesId, Items, Properties};
+use crate::topo_query::ProducerEntity;
+use crate::topo_writer::ImportStatus;
+use anyhow::Error;
+use inflector::Inflector;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// A store keeping the entities in memory, to use the importer without any server
+pub struct MemoryStore {
+    known_entities: EntitiesId,
+    content: Mutex<Content>,
+}
+
+#[derive(Default)]
+struct Content {
+    entities: BTreeMap<String, StoredEntity>,
+    /// numeric part of the last item and property ids
+    last_item: u64,
+    last_property: u64,
+}
+
+struct StoredEntity {
+    label: String,
+    aliases: Vec<String>,
+    /// the claims, in the format of the wikibase api
+    claims: Vec<Value>,
+}
+
+impl StoredEntity {
+    /// values of the property, a string or the id of an item
+    fn values<'a>(&'a self, property: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.claims
+            .iter()
+            .filter(move |c| c["mainsnak"]["property"] == property)
+            .filter_map(|c| {
+                let value = &c["mainsnak"]["datavalue"]["value"];
+                value.as_str().or_else(|| value["id"].as_str())
+            })
+    }
+
+    fn has_value(&self, property: &str, value: &str) -> bool {
+        self.values(property).any(|v| v == value)
+    }
+}
+
+impl Content {
+    fn insert(&mut self, object_type: &ObjectType, label: &str, claims: Vec<Value>) -> String {
+        let id = match object_type {
+            ObjectType::Item => {
+                self.last_item += 1;
+                format!("Q{}", self.last_item)
+            }
+            ObjectType::Property(_) => {
+                self.last_property += 1;
+                format!("P{}", self.last_property)
+            }
+        };
+        self.entities.insert(
+            id.clone(),
+            StoredEntity {
+                label: label.to_owned(),
+                aliases: vec![],
+                claims,
+            },
+        );
+        id
+    }
+
+    fn get_mut(&mut self, entity_id: &str) -> Result<&mut StoredEntity, Error> {
+        self.entities
+            .get_mut(entity_id)
+            .ok_or_else(|| ApiError::EntityNotFound(entity_id.to_owned()).into())
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStore {
+    /// create a store with all the known entities, like a wikibase after a `prepopulate`
+    pub fn new() -> Self {
+        let mut content = Content::default();
+        let topo_id_id = content.insert(
+            &ObjectType::Property(PropertyDataType::String),
+            "Topo tools id",
+            vec![],
+        );
+        // the known entities are read from the fields of the structures, so that none is forgotten
+        let mut create_all = |fields: Value, object_type: ObjectType| -> Value {
+            fields
+                .as_object()
+                .into_iter()
+                .flat_map(|fields| fields.keys())
+                .map(|topo_id| {
+                    let id = if topo_id == "topo_id_id" {
+                        topo_id_id.clone()
+                    } else {
+                        let claims = claim_string(&topo_id_id, topo_id).into_iter().collect();
+                        content.insert(&object_type, &topo_id.to_sentence_case(), claims)
+                    };
+                    (topo_id.clone(), Value::String(id))
+                })
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        };
+        let properties = create_all(
+            serde_json::to_value(Properties::default()).expect("invalid properties"),
+            ObjectType::Property(PropertyDataType::String),
+        );
+        let items = create_all(
+            serde_json::to_value(Items::default()).expect("invalid items"),
+            ObjectType::Item,
+        );
+        Self {
+            known_entities: EntitiesId {
+                properties: serde_json::from_value(properties).expect("invalid properties"),
+                items: serde_json::from_value(items).expect("invalid items"),
+            },
+            content: Mutex::new(content),
+        }
+    }
+
+    /// the entity is linked to a data source of the producer
+    fn is_produced_by(&self, content: &Content, entity: &StoredEntity, producer_id: &str) -> bool {
+        let properties = &self.known_entities.properties;
+        entity.values(&properties.data_source).any(|data_source| {
+            content
+                .entities
+                .get(data_source)
+                .map_or(false, |d| d.has_value(&properties.produced_by, producer_id))
+        })
+    }
+
+    /// find the only entity of the producer with the given type and value
+    fn find_unique(
+        &self,
+        producer_id: &str,
+        type_id: &str,
+        property: &str,
+        value: &str,
+    ) -> Result<Option<String>, Error> {
+        let content = self.content.lock().unwrap();
+        let ids: Vec<_> = content
+            .entities
+            .iter()
+            .filter(|(_, e)| {
+                e.has_value(&self.known_entities.properties.instance_of, type_id)
+                    && e.has_value(property, value)
+                    && self.is_produced_by(&content, e, producer_id)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        match ids.as_slice() {
+            [] => Ok(None),
+            [id] => Ok(Some(id.clone())),
+            _ => Err(anyhow::anyhow!(
+                "“{}” exists many times. Something is not right",
+                value
+            )),
+        }
+    }
+}
+
+impl Store for MemoryStore {
+    fn known_entities(&self) -> &EntitiesId {
+        &self.known_entities
+    }
+
+    fn find_producer(&self, producer_id: &str) -> Result<Option<String>, Error> {
+        let content = self.content.lock().unwrap();
+        Ok(content
+            .entities
+            .get(producer_id)
+            .filter(|e| {
+                e.has_value(
+                    &self.known_entities.properties.instance_of,
+                    &self.known_entities.items.producer,
+                )
+            })
+            .map(|e| e.label.clone()))
+    }
+
+    fn find_route(&self, producer_id: &str, gtfs_id: &str) -> Result<Option<String>, Error> {
+        self.find_unique(
+            producer_id,
+            &self.known_entities.items.route,
+            &self.known_entities.properties.gtfs_id,
+            gtfs_id,
+        )
+    }
+
+    fn find_stop(
+        &self,
+        producer_id: &str,
+        stop: &gtfs_structures::Stop,
+    ) -> Result<Option<String>, Error> {
+        self.find_unique(
+            producer_id,
+            self.known_entities.location_type(stop),
+            &self.known_entities.properties.gtfs_id,
+            &stop.id,
+        )
+    }
+
+    fn find_agency(
+        &self,
+        producer_id: &str,
+        agency: &gtfs_structures::Agency,
+    ) -> Result<Option<String>, Error> {
+        let properties = &self.known_entities.properties;
+        let (key_prop, key) = match &agency.id {
+            Some(id) => (&properties.gtfs_id, id),
+            None => (&properties.gtfs_name, &agency.name),
+        };
+        self.find_unique(
+            producer_id,
+            &self.known_entities.items.agency,
+            key_prop,
+            key,
+        )
+    }
+
+    fn find_producer_entities(
+        &self,
+        producer_id: &str,
+        types: &[&str],
+    ) -> Result<Vec<ProducerEntity>, Error> {
+        let properties = &self.known_entities.properties;
+        let content = self.content.lock().unwrap();
+        Ok(content
+            .entities
+            .iter()
+            .filter(|(_, e)| self.is_produced_by(&content, e, producer_id))
+            .filter_map(|(id, e)| {
+                Some(ProducerEntity {
+                    id: id.clone(),
+                    label: e.label.clone(),
+                    gtfs_id: e.values(&properties.gtfs_id).next()?.to_owned(),
+                    instance_of: e
+                        .values(&properties.instance_of)
+                        .find(|t| types.contains(t))?
+                        .to_owned(),
+                    removed: e
+                        .values(&properties.no_longer_present_since)
+                        .next()
+                        .is_some(),
+                })
+            })
+            .collect())
+    }
+
+    fn find_data_source_by_sha256(
+        &self,
+        producer_id: &str,
+        sha_256: &str,
+    ) -> Result<Option<String>, Error> {
+        let properties = &self.known_entities.properties;
+        let content = self.content.lock().unwrap();
+        Ok(content
+            .entities
+            .iter()
+            .find(|(_, e)| {
+                e.has_value(&properties.produced_by, producer_id)
+                    && e.has_value(&properties.sha_256, sha_256)
+                    && e.has_value(&properties.import_status, ImportStatus::Completed.as_str())
+            })
+            .map(|(id, _)| id.clone()))
+    }
+
+    fn create_entity(
+        &self,
+        object_type: ObjectType,
+        label: &str,
+        claims: Vec<Option<Value>>,
+    ) -> Result<String, Error> {
+        let claims = claims.into_iter().flatten().collect();
+        Ok(self
+            .content
+            .lock()
+            .unwrap()
+            .insert(&object_type, label, claims))
+    }
+
+    fn add_claims(&self, entity_id: &str, claims: Vec<Option<Value>>) -> Result<(), Error> {
+        let mut content = self.content.lock().unwrap();
+        content
+            .get_mut(entity_id)?
+            .claims
+            .extend(claims.into_iter().flatten());
+        Ok(())
+    }
+
+    fn remove_claims(&self, entity_id: &str, claims: Vec<Option<Value>>) -> Result<(), Error> {
+        let removed: Vec<_> = claims
+            .iter()
+            .flatten()
+            .filter_map(PropertyValue::from_claim)
+            .collect();
+        let mut content = self.content.lock().unwrap();
+        content.get_mut(entity_id)?.claims.retain(|claim| {
+            PropertyValue::from_claim(claim).map_or(true, |value| !removed.contains(&value))
+        });
+        Ok(())
+    }
+
+    fn set_label(&self, entity_id: &str, label: &str, alias: Option<&str>) -> Result<(), Error> {
+        let mut content = self.content.lock().unwrap();
+        let entity = content.get_mut(entity_id)?;
+        entity.label = label.to_owned();
+        if let Some(alias) = alias {
+            if !entity.aliases.iter().any(|a| a == alias) {
+                entity.aliases.push(alias.to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    fn get_entity(&self, entity_id: &str) -> Result<Entity, Error> {
+        let content = self.content.lock().unwrap();
+        let entity = content
+            .entities
+            .get(entity_id)
+            .ok_or_else(|| ApiError::EntityNotFound(entity_id.to_owned()))?;
+        let mut properties = std::collections::HashMap::new();
+        let mut qualifiers = std::collections::HashMap::new();
+        for claim in &entity.claims {
+            if let Some((property, value)) = PropertyValue::from_claim(claim) {
+                qualifiers
+                    .entry(property.clone())
+                    .or_insert_with(Vec::new)
+                    .push(claim_qualifiers(claim));
+                properties
+                    .entry(property)
+                    .or_insert_with(Vec::new)
+                    .push(value);
+            }
+        }
+        Ok(Entity {
+            id: entity_id.to_owned(),
+            label: entity.label.clone(),
+            aliases: entity.aliases.clone(),
+            properties,
+            qualifiers,
+        })
+    }
+}
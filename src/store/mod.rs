@@ -0,0 +1,83 @@
+//! Storage of the entities used by the importer
+//!
+//! The importer only relies on the `Store` trait, implemented by the wikibase instance
+//! (api for the edits and sparql for the queries), by an in-memory store
+//! that can be used without any server, like in the tests, and by a store
+//! exporting the edits as QuickStatements.
+mod memory;
+mod quick_statements;
+mod wikibase;
+
+pub use memory::MemoryStore;
+pub use quick_statements::{resolve_placeholders, QuickStatementsStore};
+pub use wikibase::WikibaseStore;
+
+use crate::clients::ObjectType;
+use crate::entity::Entity;
+use crate::known_entities::EntitiesId;
+use crate::topo_query::ProducerEntity;
+use anyhow::Error;
+
+pub trait Store: Send + Sync {
+    /// Ids of the properties and items describing the entities
+    fn known_entities(&self) -> &EntitiesId;
+
+    /// Label of the producer, None if there is no producer with this id
+    fn find_producer(&self, producer_id: &str) -> Result<Option<String>, Error>;
+
+    fn find_route(&self, producer_id: &str, gtfs_id: &str) -> Result<Option<String>, Error>;
+
+    fn find_stop(
+        &self,
+        producer_id: &str,
+        stop: &gtfs_structures::Stop,
+    ) -> Result<Option<String>, Error>;
+
+    /// Find an agency of a producer, by its gtfs id or by its name when it has no id
+    fn find_agency(
+        &self,
+        producer_id: &str,
+        agency: &gtfs_structures::Agency,
+    ) -> Result<Option<String>, Error>;
+
+    /// Find all the entities of the given types linked to a data source of the producer
+    fn find_producer_entities(
+        &self,
+        producer_id: &str,
+        types: &[&str],
+    ) -> Result<Vec<ProducerEntity>, Error>;
+
+    /// Find a data source of the producer whose import of the file with the given sha256 is completed
+    fn find_data_source_by_sha256(
+        &self,
+        producer_id: &str,
+        sha_256: &str,
+    ) -> Result<Option<String>, Error>;
+
+    /// Create an entity and return its id
+    fn create_entity(
+        &self,
+        object_type: ObjectType,
+        label: &str,
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<String, Error>;
+
+    fn add_claims(
+        &self,
+        entity_id: &str,
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<(), Error>;
+
+    /// Remove the statements of the entity with the same property and main value as the claims.
+    /// The qualifiers of the claims are not compared
+    fn remove_claims(
+        &self,
+        entity_id: &str,
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<(), Error>;
+
+    /// Set the english label of the entity, and add an english alias if given
+    fn set_label(&self, entity_id: &str, label: &str, alias: Option<&str>) -> Result<(), Error>;
+
+    fn get_entity(&self, entity_id: &str) -> Result<Entity, Error>;
+}
@@ -0,0 +1,336 @@
+use super::Store;
+use crate::clients::{ApiError, ObjectType};
+use crate::entity::Entity;
+use crate::known_entities::EntitiesId;
+use crate::topo_query::ProducerEntity;
+use anyhow::{anyhow, Context, Error};
+use serde_json::Value;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// Prefix of the ids given to the created items.
+/// `NEW3` is the third item created (the third `CREATE`) in the file
+const PLACEHOLDER_PREFIX: &str = "NEW";
+
+/// A store writing the edits as [QuickStatements](https://www.wikidata.org/wiki/Help:QuickStatements) V1 commands
+/// instead of sending them, so they can be reviewed before being applied.
+///
+/// QuickStatements V1 can only reference the item just created (with `LAST`), so the edits are split in two:
+/// the creations and the edits of the existing items are written in the output, and the statements
+/// referencing a created item (with a placeholder) are written in the pending output.
+/// The pending statements are applied once the placeholders have been replaced by the ids
+/// of the created items, with [`resolve_placeholders`].
+///
+/// The queries are made on another store, that is not edited.
+pub struct QuickStatementsStore {
+    reader: Arc<dyn Store>,
+    output: Mutex<Output>,
+}
+
+struct Output {
+    writer: Box<dyn Write + Send>,
+    pending: Box<dyn Write + Send>,
+    /// statements of the items to create, in the order of their creation.
+    /// The claims added later to an item are written in its `CREATE` block
+    created: Vec<Vec<String>>,
+}
+
+/// Position (starting at 1) of the created item of the placeholder
+fn placeholder_index(entity_id: &str) -> Option<usize> {
+    entity_id
+        .strip_prefix(PLACEHOLDER_PREFIX)
+        .and_then(|index| index.parse().ok())
+}
+
+fn is_placeholder(entity_id: &str) -> bool {
+    placeholder_index(entity_id).is_some()
+}
+
+/// The statement has a placeholder as value or as qualifier
+fn references_placeholder(statement: &str) -> bool {
+    statement.split('\t').any(is_placeholder)
+}
+
+/// A string in the QuickStatements format.
+/// There is no way to escape the quotes, tabs and line breaks, so the double quotes are replaced
+/// by single quotes and the tabs and line breaks by spaces
+fn quoted(value: &str) -> String {
+    let is_unescapable = |c: char| c == '"' || c == '\t' || c == '\n' || c == '\r';
+    if !value.contains(is_unescapable) {
+        return format!("\"{}\"", value);
+    }
+    let normalized: String = value
+        .chars()
+        .map(|c| match c {
+            '"' => '\'',
+            c if is_unescapable(c) => ' ',
+            c => c,
+        })
+        .collect();
+    log::warn!(
+        "“{}” contains a quote, a tab or a line break, it is written as “{}” in the QuickStatements",
+        value.escape_debug(),
+        normalized
+    );
+    format!("\"{}\"", normalized)
+}
+
+/// Value of a snak, in the QuickStatements format
+fn snak_value(snak: &Value) -> Result<String, Error> {
+    let value = &snak["datavalue"]["value"];
+    let quick_statements_value = match snak["datavalue"]["type"].as_str() {
+        Some("string") => value.as_str().map(quoted),
+        Some("wikibase-entityid") => value["id"].as_str().map(|id| id.to_owned()),
+        Some("globecoordinate") => value["latitude"]
+            .as_f64()
+            .and_then(|lat| Some(format!("@{}/{}", lat, value["longitude"].as_f64()?))),
+        Some("quantity") => value["amount"]
+            .as_str()
+            .map(|amount| amount.trim_start_matches('+').to_owned()),
+        _ => None,
+    };
+    quick_statements_value.ok_or_else(|| anyhow!("unsupported value {}", snak))
+}
+
+/// A claim, with its qualifiers, in the QuickStatements format (without the entity)
+fn statement(claim: &Value) -> Result<String, Error> {
+    let snak = &claim["mainsnak"];
+    let property = snak["property"]
+        .as_str()
+        .ok_or_else(|| anyhow!("no property in claim {}", claim))?;
+    let mut statement = format!("{}\t{}", property, snak_value(snak)?);
+    if let Some(qualifiers) = claim["qualifiers"].as_object() {
+        for (qualifier, snaks) in qualifiers {
+            for snak in snaks.as_array().into_iter().flatten() {
+                statement += &format!("\t{}\t{}", qualifier, snak_value(snak)?);
+            }
+        }
+    }
+    Ok(statement)
+}
+
+/// Replace the placeholders of the pending statements by the ids of the created items.
+/// `created_ids` are the ids of the items created by the `CREATE` commands, in their order
+pub fn resolve_placeholders(statements: &str, created_ids: &[String]) -> Result<String, Error> {
+    let resolve = |field: &str| -> Result<String, Error> {
+        let (prefix, id) = match field.strip_prefix('-') {
+            Some(id) => ("-", id),
+            None => ("", field),
+        };
+        match placeholder_index(id) {
+            Some(index) => created_ids
+                .get(index.wrapping_sub(1))
+                .map(|created_id| format!("{}{}", prefix, created_id))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no id for {}, only {} created items are known",
+                        id,
+                        created_ids.len()
+                    )
+                }),
+            None => Ok(field.to_owned()),
+        }
+    };
+    let mut resolved = String::new();
+    for line in statements.lines() {
+        let fields = line
+            .split('\t')
+            .map(resolve)
+            .collect::<Result<Vec<_>, Error>>()?;
+        resolved += &fields.join("\t");
+        resolved.push('\n');
+    }
+    Ok(resolved)
+}
+
+impl QuickStatementsStore {
+    /// create a store writing the commands in `writer`, the statements referencing
+    /// the created items in `pending`, and querying the entities in `reader`.
+    /// The creations are only written by `finish`
+    pub fn new(
+        reader: Arc<dyn Store>,
+        writer: Box<dyn Write + Send>,
+        pending: Box<dyn Write + Send>,
+    ) -> Self {
+        Self {
+            reader,
+            output: Mutex::new(Output {
+                writer,
+                pending,
+                created: vec![],
+            }),
+        }
+    }
+
+    /// Write the statements of an entity, in the block of the entity if it is created by the file
+    fn write_statements(&self, entity_id: &str, statements: Vec<String>) -> Result<(), Error> {
+        let mut output = self.output.lock().unwrap();
+        let output = &mut *output;
+        for statement in statements {
+            let written = if references_placeholder(&statement) {
+                writeln!(output.pending, "{}\t{}", entity_id, statement)
+            } else if let Some(index) = placeholder_index(entity_id) {
+                index
+                    .checked_sub(1)
+                    .and_then(|index| output.created.get_mut(index))
+                    .ok_or_else(|| anyhow!("unknown item {}", entity_id))?
+                    .push(statement);
+                Ok(())
+            } else {
+                writeln!(output.writer, "{}\t{}", entity_id, statement)
+            };
+            written.context("impossible to write the statements")?;
+        }
+        output
+            .writer
+            .flush()
+            .and_then(|_| output.pending.flush())
+            .context("impossible to write the statements")
+    }
+
+    /// Write the `CREATE` commands of the created items, with their claims.
+    /// To call once, at the end of the import
+    pub fn finish(&self) -> Result<(), Error> {
+        let mut output = self.output.lock().unwrap();
+        let output = &mut *output;
+        for statements in &output.created {
+            writeln!(output.writer, "CREATE").context("impossible to write the statements")?;
+            for statement in statements {
+                writeln!(output.writer, "LAST\t{}", statement)
+                    .context("impossible to write the statements")?;
+            }
+        }
+        output
+            .writer
+            .flush()
+            .and_then(|_| output.pending.flush())
+            .context("impossible to write the statements")
+    }
+}
+
+impl Store for QuickStatementsStore {
+    fn known_entities(&self) -> &EntitiesId {
+        self.reader.known_entities()
+    }
+
+    fn find_producer(&self, producer_id: &str) -> Result<Option<String>, Error> {
+        self.reader.find_producer(producer_id)
+    }
+
+    fn find_route(&self, producer_id: &str, gtfs_id: &str) -> Result<Option<String>, Error> {
+        self.reader.find_route(producer_id, gtfs_id)
+    }
+
+    fn find_stop(
+        &self,
+        producer_id: &str,
+        stop: &gtfs_structures::Stop,
+    ) -> Result<Option<String>, Error> {
+        self.reader.find_stop(producer_id, stop)
+    }
+
+    fn find_agency(
+        &self,
+        producer_id: &str,
+        agency: &gtfs_structures::Agency,
+    ) -> Result<Option<String>, Error> {
+        self.reader.find_agency(producer_id, agency)
+    }
+
+    fn find_producer_entities(
+        &self,
+        producer_id: &str,
+        types: &[&str],
+    ) -> Result<Vec<ProducerEntity>, Error> {
+        self.reader.find_producer_entities(producer_id, types)
+    }
+
+    fn find_data_source_by_sha256(
+        &self,
+        producer_id: &str,
+        sha_256: &str,
+    ) -> Result<Option<String>, Error> {
+        self.reader.find_data_source_by_sha256(producer_id, sha_256)
+    }
+
+    fn create_entity(
+        &self,
+        object_type: ObjectType,
+        label: &str,
+        claims: Vec<Option<Value>>,
+    ) -> Result<String, Error> {
+        if let ObjectType::Property(_) = object_type {
+            return Err(anyhow!(
+                "impossible to create the property “{}”, QuickStatements only creates items",
+                label
+            ));
+        }
+        let mut statements = vec![format!("Len\t{}", quoted(label))];
+        for claim in claims.iter().flatten() {
+            statements.push(statement(claim)?);
+        }
+        let id = {
+            let mut output = self.output.lock().unwrap();
+            output.created.push(vec![]);
+            format!("{}{}", PLACEHOLDER_PREFIX, output.created.len())
+        };
+        self.write_statements(&id, statements)?;
+        Ok(id)
+    }
+
+    fn add_claims(&self, entity_id: &str, claims: Vec<Option<Value>>) -> Result<(), Error> {
+        let statements = claims
+            .iter()
+            .flatten()
+            .map(statement)
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.write_statements(entity_id, statements)
+    }
+
+    fn remove_claims(&self, entity_id: &str, claims: Vec<Option<Value>>) -> Result<(), Error> {
+        if is_placeholder(entity_id) {
+            return Err(anyhow!(
+                "impossible to remove claims of {}, it is created by the QuickStatements",
+                entity_id
+            ));
+        }
+        // a statement is removed with its main value, without the qualifiers
+        let lines = claims
+            .iter()
+            .flatten()
+            .map(|claim| {
+                let snak = &claim["mainsnak"];
+                Ok(format!(
+                    "-{}\t{}\t{}",
+                    entity_id,
+                    snak["property"].as_str().unwrap_or_default(),
+                    snak_value(snak)?
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut output = self.output.lock().unwrap();
+        for line in lines {
+            writeln!(output.writer, "{}", line).context("impossible to write the statements")?;
+        }
+        output
+            .writer
+            .flush()
+            .context("impossible to write the statements")
+    }
+
+    fn set_label(&self, entity_id: &str, label: &str, alias: Option<&str>) -> Result<(), Error> {
+        let mut statements = vec![format!("Len\t{}", quoted(label))];
+        if let Some(alias) = alias {
+            statements.push(format!("Aen\t{}", quoted(alias)));
+        }
+        self.write_statements(entity_id, statements)
+    }
+
+    fn get_entity(&self, entity_id: &str) -> Result<Entity, Error> {
+        if is_placeholder(entity_id) {
+            // the entity only exists in the QuickStatements file
+            return Err(ApiError::EntityNotFound(entity_id.to_owned()).into());
+        }
+        self.reader.get_entity(entity_id)
+    }
+}
@@ -0,0 +1,148 @@
+use super::Store;
+use crate::clients::{ApiClient, Credentials, ObjectType, RetryPolicy};
+use crate::entity::Entity;
+use crate::known_entities::EntitiesId;
+use crate::topo_query::{ProducerEntity, TopoQuery};
+use anyhow::{Context, Error};
+
+/// A wikibase instance, edited with its api and queried with its sparql endpoint
+pub struct WikibaseStore {
+    pub query: TopoQuery,
+    /// Client of the api, there is none if the store is read-only
+    pub api: Option<ApiClient>,
+}
+
+impl WikibaseStore {
+    /// create a store, the edits are anonymous if no credentials are given
+    pub fn new(
+        api_endpoint: &str,
+        sparql_endpoint: &str,
+        topo_id_id: &str,
+        credentials: Option<Credentials>,
+    ) -> Result<Self, Error> {
+        let mut store = Self::read_only(sparql_endpoint, topo_id_id)?;
+        store.api = Some(
+            ApiClient::with_credentials(api_endpoint, credentials)
+                .context("impossible to create api client")?,
+        );
+        Ok(store)
+    }
+
+    /// create a store that can only be queried
+    pub fn read_only(sparql_endpoint: &str, topo_id_id: &str) -> Result<Self, Error> {
+        Ok(Self {
+            query: TopoQuery::new(sparql_endpoint, topo_id_id)
+                .context("impossible to create query client")?,
+            api: None,
+        })
+    }
+
+    /// set the retry policy of the api and sparql clients
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        if let Some(api) = &mut self.api {
+            api.set_retry_policy(retry.clone());
+        }
+        self.query.client.set_retry_policy(retry);
+    }
+
+    fn api(&self) -> Result<&ApiClient, Error> {
+        self.api
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("the store is read-only, no api endpoint given"))
+    }
+
+    /// Wait until the query service has loaded all the edits made with the api
+    pub fn wait_for_query_service(&self, timeout: std::time::Duration) -> Result<(), Error> {
+        match self.api.as_ref().and_then(|api| api.last_revision()) {
+            Some((entity_id, revision)) => {
+                log::info!(
+                    "waiting for the query service to load revision {}",
+                    revision
+                );
+                self.query.wait_for_revision(&entity_id, revision, timeout)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Store for WikibaseStore {
+    fn known_entities(&self) -> &EntitiesId {
+        &self.query.known_entities
+    }
+
+    fn find_producer(&self, producer_id: &str) -> Result<Option<String>, Error> {
+        Ok(self.query.get_producer_label(producer_id)?)
+    }
+
+    fn find_route(&self, producer_id: &str, gtfs_id: &str) -> Result<Option<String>, Error> {
+        Ok(self.query.find_route(producer_id, gtfs_id)?)
+    }
+
+    fn find_stop(
+        &self,
+        producer_id: &str,
+        stop: &gtfs_structures::Stop,
+    ) -> Result<Option<String>, Error> {
+        Ok(self.query.find_stop(producer_id, stop)?)
+    }
+
+    fn find_agency(
+        &self,
+        producer_id: &str,
+        agency: &gtfs_structures::Agency,
+    ) -> Result<Option<String>, Error> {
+        Ok(self.query.find_agency(producer_id, agency)?)
+    }
+
+    fn find_producer_entities(
+        &self,
+        producer_id: &str,
+        types: &[&str],
+    ) -> Result<Vec<ProducerEntity>, Error> {
+        Ok(self.query.find_producer_entities(producer_id, types)?)
+    }
+
+    fn find_data_source_by_sha256(
+        &self,
+        producer_id: &str,
+        sha_256: &str,
+    ) -> Result<Option<String>, Error> {
+        Ok(self
+            .query
+            .find_data_source_by_sha256(producer_id, sha_256)?)
+    }
+
+    fn create_entity(
+        &self,
+        object_type: ObjectType,
+        label: &str,
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<String, Error> {
+        Ok(self.api()?.create_object(object_type, label, claims)?)
+    }
+
+    fn add_claims(
+        &self,
+        entity_id: &str,
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<(), Error> {
+        Ok(self.api()?.add_claims(entity_id, claims)?)
+    }
+
+    fn remove_claims(
+        &self,
+        entity_id: &str,
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<(), Error> {
+        Ok(self.api()?.remove_claims(entity_id, claims)?)
+    }
+
+    fn set_label(&self, entity_id: &str, label: &str, alias: Option<&str>) -> Result<(), Error> {
+        Ok(self.api()?.set_label(entity_id, label, alias)?)
+    }
+
+    fn get_entity(&self, entity_id: &str) -> Result<Entity, Error> {
+        Ok(self.api()?.get_entity(entity_id)?)
+    }
+}
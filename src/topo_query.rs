@@ -1,13 +1,78 @@
+use crate::clients::sparql_builder::{literal, prefixed, var, wd, wdt, Expression, WhereClause};
 use crate::clients::sparql_client::{read_id_from_url, SparqlClient};
 use crate::known_entities::{EntitiesId, Items, Properties};
+use crate::topo_writer::ImportStatus;
 use anyhow::Context;
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
+/// Number of results by page when querying lots of entities
+const PAGE_SIZE: usize = 10_000;
+
 pub struct TopoQuery {
     pub client: SparqlClient,
     pub known_entities: EntitiesId,
 }
 
+/// An entity linked to one of the data sources of a producer
+#[derive(Debug, Clone)]
+pub struct ProducerEntity {
+    pub id: String,
+    pub label: String,
+    pub gtfs_id: String,
+    /// id of the type of the entity
+    pub instance_of: String,
+    /// true if the entity has been marked as no longer present in the producer's data
+    pub removed: bool,
+}
+
+/// Entities already in the database, indexed by their type and their gtfs id
+#[derive(Debug, Default)]
+pub struct ExistingEntities {
+    by_key: HashMap<(String, String), Vec<String>>,
+    /// entities marked as no longer present in the producer's data
+    removed: HashSet<String>,
+}
+
+impl ExistingEntities {
+    /// Add an entity not yet visible in the query service
+    pub fn add(&mut self, type_id: &str, gtfs_id: &str, id: &str) {
+        let ids = self
+            .by_key
+            .entry((type_id.to_owned(), gtfs_id.to_owned()))
+            .or_insert_with(Vec::new);
+        if !ids.iter().any(|i| i == id) {
+            ids.push(id.to_owned());
+        }
+    }
+
+    /// The entity has been marked as no longer present in the producer's data
+    pub fn add_removed(&mut self, id: &str) {
+        self.removed.insert(id.to_owned());
+    }
+
+    pub fn is_removed(&self, id: &str) -> bool {
+        self.removed.contains(id)
+    }
+
+    /// Find the id of the entity with the given type and gtfs id
+    pub fn get(&self, type_id: &str, gtfs_id: &str) -> Result<Option<String>, QueryError> {
+        match self
+            .by_key
+            .get(&(type_id.to_owned(), gtfs_id.to_owned()))
+            .map(|ids| ids.as_slice())
+        {
+            None | Some([]) => Ok(None),
+            Some([id]) => Ok(Some(id.clone())),
+            Some(_) => Err(QueryError::Duplicate(format!(
+                "“{}” exists many times. Something is not right",
+                gtfs_id
+            ))),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum QueryError {
     #[error("No entity with topo id {0} found")]
@@ -42,20 +107,27 @@ impl TopoQuery {
         log::trace!("Finding route {} of producer {}", gtfs_id, producer_id);
         let items = self.client.sparql(
             &["?route", "?routeLabel", "?gtfs_id"],
-            &format!(
-                "?route wdt:{instance_of} wd:{route}.
-                 ?route wdt:{gtfs_id_prop} \"{gtfs_id}\".
-                 ?route wdt:{data_source} ?data_source.
-                 ?data_source wdt:{producer_prop} wd:{producer_id}.
-                 ",
-                instance_of = self.known_entities.properties.instance_of,
-                route = self.known_entities.items.route,
-                gtfs_id_prop = self.known_entities.properties.gtfs_id,
-                producer_prop = self.known_entities.properties.produced_by,
-                data_source = self.known_entities.properties.data_source,
-                gtfs_id = gtfs_id,
-                producer_id = producer_id,
-            ),
+            &WhereClause::new()
+                .triple(
+                    var("route"),
+                    wdt(&self.known_entities.properties.instance_of),
+                    wd(&self.known_entities.items.route),
+                )
+                .triple(
+                    var("route"),
+                    wdt(&self.known_entities.properties.gtfs_id),
+                    literal(gtfs_id),
+                )
+                .triple(
+                    var("route"),
+                    wdt(&self.known_entities.properties.data_source),
+                    var("data_source"),
+                )
+                .triple(
+                    var("data_source"),
+                    wdt(&self.known_entities.properties.produced_by),
+                    wd(producer_id),
+                ),
         )?;
 
         match items.as_slice() {
@@ -81,21 +153,32 @@ impl TopoQuery {
         );
         let items = self.client.sparql(
             &["?stop", "?stopLabel", "?stopName", "?gtfs_id"],
-            &format!(
-                "?stop wdt:{instance_of} wd:{stop_type}.
-                 ?stop wdt:{gtfs_id_prop} \"{gtfs_id}\".
-                 ?stop wdt:{data_source} ?data_source.
-                 ?data_source wdt:{producer_prop} wd:{producer_id}.
-                 ?stop wdt:{gtfs_name} ?stop_name.",
-                instance_of = self.known_entities.properties.instance_of,
-                stop_type = self.known_entities.location_type(stop),
-                gtfs_id_prop = self.known_entities.properties.gtfs_id,
-                producer_prop = self.known_entities.properties.produced_by,
-                gtfs_name = self.known_entities.properties.gtfs_name,
-                data_source = self.known_entities.properties.data_source,
-                gtfs_id = stop.id,
-                producer_id = producer_id,
-            ),
+            &WhereClause::new()
+                .triple(
+                    var("stop"),
+                    wdt(&self.known_entities.properties.instance_of),
+                    wd(self.known_entities.location_type(stop)),
+                )
+                .triple(
+                    var("stop"),
+                    wdt(&self.known_entities.properties.gtfs_id),
+                    literal(&stop.id),
+                )
+                .triple(
+                    var("stop"),
+                    wdt(&self.known_entities.properties.data_source),
+                    var("data_source"),
+                )
+                .triple(
+                    var("data_source"),
+                    wdt(&self.known_entities.properties.produced_by),
+                    wd(producer_id),
+                )
+                .triple(
+                    var("stop"),
+                    wdt(&self.known_entities.properties.gtfs_name),
+                    var("stop_name"),
+                ),
         )?;
 
         match items.as_slice() {
@@ -108,16 +191,329 @@ impl TopoQuery {
         }
     }
 
+    /// Find an agency of a producer
+    /// The agency is found by its gtfs id, or by its name when it has no id
+    /// (the agency_id is optional in a GTFS with only one agency)
+    pub fn find_agency(
+        &self,
+        producer_id: &str,
+        agency: &gtfs_structures::Agency,
+    ) -> Result<Option<String>, QueryError> {
+        log::trace!("Finding agency {} of producer {}", agency.name, producer_id);
+        let (key_prop, key) = match &agency.id {
+            Some(id) => (&self.known_entities.properties.gtfs_id, id),
+            None => (&self.known_entities.properties.gtfs_name, &agency.name),
+        };
+        let items = self.client.sparql(
+            &["?agency"],
+            &WhereClause::new()
+                .triple(
+                    var("agency"),
+                    wdt(&self.known_entities.properties.instance_of),
+                    wd(&self.known_entities.items.agency),
+                )
+                .triple(var("agency"), wdt(key_prop), literal(key))
+                .triple(
+                    var("agency"),
+                    wdt(&self.known_entities.properties.data_source),
+                    var("data_source"),
+                )
+                .triple(
+                    var("data_source"),
+                    wdt(&self.known_entities.properties.produced_by),
+                    wd(producer_id),
+                ),
+        )?;
+
+        match items.as_slice() {
+            [] => Ok(None),
+            [item] => Ok(item.get("agency").and_then(|u| read_id_from_url(u))),
+            _ => Err(QueryError::Duplicate(format!(
+                "Agency “{}” exists many times. Something is not right",
+                key
+            ))),
+        }
+    }
+
+    /// Find all the entities of the given types linked to a data source of the producer
+    pub fn find_producer_entities(
+        &self,
+        producer_id: &str,
+        types: &[&str],
+    ) -> Result<Vec<ProducerEntity>, QueryError> {
+        log::debug!("Finding all entities of producer {}", producer_id);
+        let items = self.client.sparql_paged(
+            &[
+                "?entity",
+                "?entityLabel",
+                "?gtfs_id",
+                "?type",
+                "?removed_in",
+            ],
+            &WhereClause::new()
+                .values(var("type"), types.iter().map(|t| wd(t)).collect())
+                .triple(
+                    var("entity"),
+                    wdt(&self.known_entities.properties.instance_of),
+                    var("type"),
+                )
+                .triple(
+                    var("entity"),
+                    wdt(&self.known_entities.properties.gtfs_id),
+                    var("gtfs_id"),
+                )
+                .triple(
+                    var("entity"),
+                    wdt(&self.known_entities.properties.data_source),
+                    var("data_source"),
+                )
+                .triple(
+                    var("data_source"),
+                    wdt(&self.known_entities.properties.produced_by),
+                    wd(producer_id),
+                )
+                .optional(WhereClause::new().triple(
+                    var("entity"),
+                    wdt(&self.known_entities.properties.no_longer_present_since),
+                    var("removed_in"),
+                )),
+            // an entity has several rows if it has several gtfs ids or types,
+            // the label is the only one of the entity
+            &[
+                var("entity"),
+                var("gtfs_id"),
+                var("type"),
+                var("removed_in"),
+            ],
+            PAGE_SIZE,
+        )?;
+
+        // an entity can be returned several times (if it is linked to several data sources)
+        Ok(items
+            .into_iter()
+            .filter_map(|mut item| {
+                Some(ProducerEntity {
+                    id: read_id_from_url(&item.remove("entity")?)?,
+                    label: item.remove("entityLabel").unwrap_or_default(),
+                    gtfs_id: item.remove("gtfs_id")?,
+                    instance_of: read_id_from_url(&item.remove("type")?)?,
+                    removed: item.contains_key("removed_in"),
+                })
+            })
+            .unique_by(|e| e.id.clone())
+            .collect())
+    }
+
+    /// Find a data source of the producer whose import of the file with the given sha256 is completed.
+    /// The data sources of failed or interrupted imports are ignored, the file needs to be imported again
+    pub fn find_data_source_by_sha256(
+        &self,
+        producer_id: &str,
+        sha_256: &str,
+    ) -> Result<Option<String>, QueryError> {
+        let items = self.client.sparql(
+            &["?data_source"],
+            &WhereClause::new()
+                .triple(
+                    var("data_source"),
+                    wdt(&self.known_entities.properties.produced_by),
+                    wd(producer_id),
+                )
+                .triple(
+                    var("data_source"),
+                    wdt(&self.known_entities.properties.sha_256),
+                    literal(sha_256),
+                )
+                .triple(
+                    var("data_source"),
+                    wdt(&self.known_entities.properties.import_status),
+                    literal(ImportStatus::Completed.as_str()),
+                ),
+        )?;
+        // there can be several data sources if the file has been imported with `--force`
+        Ok(items
+            .iter()
+            .filter_map(|item| item.get("data_source").and_then(|u| read_id_from_url(u)))
+            .next())
+    }
+
+    /// Find the data sources that no entity references
+    /// (typically the data sources of imports that failed before creating anything)
+    /// If a producer is given, only its data sources are returned.
+    /// A running or interrupted import has no entity yet either, so only the data sources
+    /// whose import failed or that have not been modified for `grace_period` are returned.
+    /// The latest completed data source of each producer is kept even if nothing references it,
+    /// it records the sha256 of the last imported file to not import it again
+    pub fn find_unreferenced_data_sources(
+        &self,
+        producer_id: Option<&str>,
+        grace_period: chrono::Duration,
+    ) -> Result<Vec<String>, QueryError> {
+        let properties = &self.known_entities.properties;
+        let producer = producer_id.map(wd).unwrap_or_else(|| var("producer"));
+        let items = self.client.sparql(
+            &["?data_source", "?status", "?modified"],
+            &WhereClause::new()
+                .triple(var("data_source"), wdt(&properties.produced_by), producer)
+                .triple(
+                    var("data_source"),
+                    prefixed("schema", "dateModified"),
+                    var("modified"),
+                )
+                .optional(WhereClause::new().triple(
+                    var("data_source"),
+                    wdt(&properties.import_status),
+                    var("status"),
+                ))
+                .filter(Expression::NotExists(
+                    WhereClause::new()
+                        .values(
+                            var("link"),
+                            vec![
+                                wdt(&properties.data_source),
+                                wdt(&properties.first_seen_in),
+                                wdt(&properties.seen_in),
+                                wdt(&properties.no_longer_present_since),
+                            ],
+                        )
+                        .triple(var("entity"), var("link"), var("data_source")),
+                )),
+        )?;
+        let latest_completed = self.find_latest_completed_data_sources(producer_id)?;
+        let limit = chrono::Utc::now() - grace_period;
+        let is_deletable = |item: &HashMap<String, String>| {
+            let failed = item
+                .get("status")
+                .map_or(false, |s| s == ImportStatus::Failed.as_str());
+            let old = item
+                .get("modified")
+                .and_then(|m| chrono::DateTime::parse_from_rfc3339(m).ok())
+                .map_or(false, |m| m < limit);
+            failed || old
+        };
+        Ok(items
+            .iter()
+            .filter(|item| is_deletable(item))
+            .filter_map(|item| item.get("data_source").and_then(|u| read_id_from_url(u)))
+            .filter(|id| !latest_completed.contains(id))
+            .unique()
+            .collect())
+    }
+
+    /// Find the most recently modified data source whose import is completed, for each producer
+    /// (or only for the given producer)
+    fn find_latest_completed_data_sources(
+        &self,
+        producer_id: Option<&str>,
+    ) -> Result<HashSet<String>, QueryError> {
+        let properties = &self.known_entities.properties;
+        let mut where_clause = WhereClause::new();
+        if let Some(producer_id) = producer_id {
+            where_clause = where_clause.values(var("producer"), vec![wd(producer_id)]);
+        }
+        let items = self.client.sparql(
+            &["?producer", "?data_source", "?modified"],
+            &where_clause
+                .triple(
+                    var("data_source"),
+                    wdt(&properties.produced_by),
+                    var("producer"),
+                )
+                .triple(
+                    var("data_source"),
+                    wdt(&properties.import_status),
+                    literal(ImportStatus::Completed.as_str()),
+                )
+                .triple(
+                    var("data_source"),
+                    prefixed("schema", "dateModified"),
+                    var("modified"),
+                ),
+        )?;
+        let mut latest: HashMap<String, (chrono::DateTime<chrono::FixedOffset>, String)> =
+            HashMap::new();
+        for item in &items {
+            let (producer, data_source, modified) = match (
+                item.get("producer"),
+                item.get("data_source").and_then(|u| read_id_from_url(u)),
+                item.get("modified")
+                    .and_then(|m| chrono::DateTime::parse_from_rfc3339(m).ok()),
+            ) {
+                (Some(producer), Some(data_source), Some(modified)) => {
+                    (producer, data_source, modified)
+                }
+                _ => continue,
+            };
+            let is_latest =
+                latest
+                    .get(producer)
+                    .map_or(true, |(latest_modified, latest_data_source)| {
+                        // the id breaks the ties, to always keep the same data source
+                        (modified, &data_source) > (*latest_modified, latest_data_source)
+                    });
+            if is_latest {
+                latest.insert(producer.to_owned(), (modified, data_source));
+            }
+        }
+        Ok(latest
+            .into_iter()
+            .map(|(_, (_, data_source))| data_source)
+            .collect())
+    }
+
+    /// Wait until the query service has loaded the given revision of an entity
+    pub fn wait_for_revision(
+        &self,
+        entity_id: &str,
+        revision: u64,
+        timeout: std::time::Duration,
+    ) -> Result<(), anyhow::Error> {
+        let start = std::time::Instant::now();
+        loop {
+            let items = self.client.sparql(
+                &["?version"],
+                &WhereClause::new().triple(
+                    wd(entity_id),
+                    prefixed("schema", "version"),
+                    var("version"),
+                ),
+            )?;
+            let version = items
+                .iter()
+                .filter_map(|item| item.get("version")?.parse::<u64>().ok())
+                .max();
+            if version.map_or(false, |v| v >= revision) {
+                log::info!("the query service is up to date with revision {}", revision);
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                return Err(anyhow::anyhow!(
+                    "the query service has not loaded revision {} of {} after {:?}",
+                    revision,
+                    entity_id,
+                    timeout
+                ));
+            }
+            log::debug!(
+                "waiting for the query service to load revision {} of {} (currently {:?})",
+                revision,
+                entity_id,
+                version
+            );
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+
     pub fn get_producer_label(&self, producer_id: &str) -> Result<Option<String>, QueryError> {
         let mut items = self.client.sparql(
             &["?label"],
-            &format!(
-                "wd:{producer_id} wdt:{instance_of} wd:{producer};
-                                  rdfs:label ?label.",
-                producer_id = producer_id,
-                instance_of = self.known_entities.properties.instance_of,
-                producer = self.known_entities.items.producer
-            ),
+            &WhereClause::new()
+                .triple(
+                    wd(producer_id),
+                    wdt(&self.known_entities.properties.instance_of),
+                    wd(&self.known_entities.items.producer),
+                )
+                .triple(wd(producer_id), prefixed("rdfs", "label"), var("label")),
         )?;
         match items.as_mut_slice() {
             [] => Ok(None),
@@ -137,11 +533,7 @@ fn find_entity_by_topo_id(
 ) -> Result<String, QueryError> {
     let items = client.sparql(
         &["?item_id"],
-        &format!(
-            "?item_id wdt:{topo_id_id} '{item_topo_id}'",
-            topo_id_id = topo_id_id,
-            item_topo_id = item_topo_id
-        ),
+        &WhereClause::new().triple(var("item_id"), wdt(topo_id_id), literal(item_topo_id)),
     )?;
 
     let id = match items.as_slice() {
@@ -174,6 +566,21 @@ fn discover_known_entities(
             stop_entrance: find_entity_by_topo_id(client, "stop_entrance", topo_id_id)?,
             stop_generic_node: find_entity_by_topo_id(client, "stop_generic_node", topo_id_id)?,
             stop_boarding_area: find_entity_by_topo_id(client, "stop_boarding_area", topo_id_id)?,
+            agency: find_entity_by_topo_id(client, "agency", topo_id_id)?,
+            level: find_entity_by_topo_id(client, "level", topo_id_id)?,
+            route_pattern: find_entity_by_topo_id(client, "route_pattern", topo_id_id)?,
+            wheelchair_accessibility: find_entity_by_topo_id(
+                client,
+                "wheelchair_accessibility",
+                topo_id_id,
+            )?,
+            unknown_accessibility: find_entity_by_topo_id(
+                client,
+                "unknown_accessibility",
+                topo_id_id,
+            )?,
+            accessible: find_entity_by_topo_id(client, "accessible", topo_id_id)?,
+            not_accessible: find_entity_by_topo_id(client, "not_accessible", topo_id_id)?,
         },
         properties: Properties {
             topo_id_id: topo_id_id.to_string(),
@@ -182,17 +589,59 @@ fn discover_known_entities(
             gtfs_short_name: find_entity_by_topo_id(client, "gtfs_short_name", topo_id_id)?,
             gtfs_long_name: find_entity_by_topo_id(client, "gtfs_long_name", topo_id_id)?,
             gtfs_name: find_entity_by_topo_id(client, "gtfs_name", topo_id_id)?,
+            gtfs_description: find_entity_by_topo_id(client, "gtfs_description", topo_id_id)?,
             gtfs_id: find_entity_by_topo_id(client, "gtfs_id", topo_id_id)?,
             first_seen_in: find_entity_by_topo_id(client, "first_seen_in", topo_id_id)?,
+            seen_in: find_entity_by_topo_id(client, "seen_in", topo_id_id)?,
+            no_longer_present_since: find_entity_by_topo_id(
+                client,
+                "no_longer_present_since",
+                topo_id_id,
+            )?,
             data_source: find_entity_by_topo_id(client, "data_source", topo_id_id)?,
             source: find_entity_by_topo_id(client, "source", topo_id_id)?,
             file_format: find_entity_by_topo_id(client, "file_format", topo_id_id)?,
             sha_256: find_entity_by_topo_id(client, "sha_256", topo_id_id)?,
+            import_status: find_entity_by_topo_id(client, "import_status", topo_id_id)?,
+            route_count: find_entity_by_topo_id(client, "route_count", topo_id_id)?,
+            stop_count: find_entity_by_topo_id(client, "stop_count", topo_id_id)?,
+            relation_count: find_entity_by_topo_id(client, "relation_count", topo_id_id)?,
+            warning_count: find_entity_by_topo_id(client, "warning_count", topo_id_id)?,
             has_physical_mode: find_entity_by_topo_id(client, "has_physical_mode", topo_id_id)?,
             tool_version: find_entity_by_topo_id(client, "tool_version", topo_id_id)?,
             part_of: find_entity_by_topo_id(client, "part_of", topo_id_id)?,
             connecting_line: find_entity_by_topo_id(client, "connecting_line", topo_id_id)?,
             coordinate_location: find_entity_by_topo_id(client, "coordinate_location", topo_id_id)?,
+            operated_by: find_entity_by_topo_id(client, "operated_by", topo_id_id)?,
+            url: find_entity_by_topo_id(client, "url", topo_id_id)?,
+            timezone: find_entity_by_topo_id(client, "timezone", topo_id_id)?,
+            phone: find_entity_by_topo_id(client, "phone", topo_id_id)?,
+            transfer_to: find_entity_by_topo_id(client, "transfer_to", topo_id_id)?,
+            gtfs_transfer_type: find_entity_by_topo_id(client, "gtfs_transfer_type", topo_id_id)?,
+            min_transfer_time: find_entity_by_topo_id(client, "min_transfer_time", topo_id_id)?,
+            on_level: find_entity_by_topo_id(client, "on_level", topo_id_id)?,
+            level_index: find_entity_by_topo_id(client, "level_index", topo_id_id)?,
+            pathway_to: find_entity_by_topo_id(client, "pathway_to", topo_id_id)?,
+            gtfs_pathway_mode: find_entity_by_topo_id(client, "gtfs_pathway_mode", topo_id_id)?,
+            gtfs_is_bidirectional: find_entity_by_topo_id(
+                client,
+                "gtfs_is_bidirectional",
+                topo_id_id,
+            )?,
+            length: find_entity_by_topo_id(client, "length", topo_id_id)?,
+            traversal_time: find_entity_by_topo_id(client, "traversal_time", topo_id_id)?,
+            color: find_entity_by_topo_id(client, "color", topo_id_id)?,
+            text_color: find_entity_by_topo_id(client, "text_color", topo_id_id)?,
+            sort_order: find_entity_by_topo_id(client, "sort_order", topo_id_id)?,
+            gtfs_code: find_entity_by_topo_id(client, "gtfs_code", topo_id_id)?,
+            platform_code: find_entity_by_topo_id(client, "platform_code", topo_id_id)?,
+            gtfs_zone_id: find_entity_by_topo_id(client, "gtfs_zone_id", topo_id_id)?,
+            wheelchair_boarding: find_entity_by_topo_id(client, "wheelchair_boarding", topo_id_id)?,
+            pattern_of: find_entity_by_topo_id(client, "pattern_of", topo_id_id)?,
+            gtfs_direction_id: find_entity_by_topo_id(client, "gtfs_direction_id", topo_id_id)?,
+            gtfs_headsign: find_entity_by_topo_id(client, "gtfs_headsign", topo_id_id)?,
+            has_stop: find_entity_by_topo_id(client, "has_stop", topo_id_id)?,
+            stop_sequence: find_entity_by_topo_id(client, "stop_sequence", topo_id_id)?,
         },
     })
 }
@@ -1,19 +1,112 @@
-use crate::clients::api_client::{claim_coord, claim_item, claim_string, ApiClient};
+use crate::clients::api_client::{
+    claim_coord, claim_item, claim_quantity, claim_string, with_qualifiers,
+};
 use crate::clients::ObjectType;
+use crate::entity::{Entity, PropertyValue};
+use crate::gtfs_extra::{RouteDetails, RoutePattern, StopDetails};
+use crate::import_report::{EntityKind, ImportReport};
 use crate::known_entities::EntitiesId;
+use crate::store::Store;
 use anyhow::Context;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
+/// Claims to add to already existing entities.
+/// The claims are grouped by entity to edit each entity only once.
+#[derive(Default)]
+pub struct ClaimsBatch {
+    claims: BTreeMap<String, Vec<Option<serde_json::Value>>>,
+}
+
+impl ClaimsBatch {
+    pub fn add(&mut self, entity_id: &str, claim: Option<serde_json::Value>) {
+        self.claims
+            .entry(entity_id.to_owned())
+            .or_insert_with(Vec::new)
+            .push(claim);
+    }
+
+    /// only keep the claims of the entities matching the predicate
+    pub fn retain(&mut self, mut f: impl FnMut(&str) -> bool) {
+        self.claims.retain(|entity_id, _| f(entity_id));
+    }
+
+    /// ids of the entities edited by the batch
+    pub fn entities(&self) -> impl Iterator<Item = &str> {
+        self.claims.keys().map(String::as_str)
+    }
+
+    /// remove the claims already on the entity, the entity is not edited if it already has all of them.
+    /// See `Entity::has_claim` for `key_qualifier`
+    pub fn remove_existing(&mut self, entity: &Entity, key_qualifier: &str) {
+        let is_empty = match self.claims.get_mut(&entity.id) {
+            Some(claims) => {
+                claims.retain(|claim| {
+                    claim
+                        .as_ref()
+                        .map_or(false, |claim| !entity.has_claim(claim, key_qualifier))
+                });
+                claims.is_empty()
+            }
+            None => false,
+        };
+        if is_empty {
+            self.claims.remove(&entity.id);
+        }
+    }
+
+    /// iterate over all the (entity id, claim) of the batch
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &serde_json::Value)> {
+        self.claims.iter().flat_map(|(entity_id, claims)| {
+            claims
+                .iter()
+                .filter_map(move |c| c.as_ref().map(|c| (entity_id.as_str(), c)))
+        })
+    }
+}
+
+/// Label of a route in the database, the producer name is added to make it easier to distinguish
+pub fn route_label(route: &gtfs_structures::Route, producer_name: &str) -> String {
+    let route_name = if !route.long_name.is_empty() {
+        route.long_name.as_str()
+    } else {
+        route.short_name.as_str()
+    };
+
+    format!("{:?} {} ({})", route.route_type, route_name, producer_name)
+}
+
+/// Status of the import of a data source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStatus {
+    Completed,
+    /// The import stopped partway or some entities could not be imported,
+    /// the data source might be incomplete
+    Failed,
+}
+
+impl ImportStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ImportStatus::Completed => "completed",
+            ImportStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Build the claims of the GTFS entities and write them in the store
 pub struct TopoWriter {
-    pub client: ApiClient,
+    pub store: Arc<dyn Store>,
     pub known_entities: EntitiesId,
 }
 
 impl TopoWriter {
-    pub fn new(endpoint: &str, known_entities: EntitiesId) -> Result<Self, anyhow::Error> {
-        Ok(Self {
-            client: ApiClient::new(endpoint).context("impossible to create api client")?,
-            known_entities,
-        })
+    pub fn new(store: Arc<dyn Store>) -> Self {
+        Self {
+            known_entities: store.known_entities().clone(),
+            store,
+        }
     }
 
     pub fn insert_data_source(
@@ -38,31 +131,115 @@ impl TopoWriter {
             claims.push(claim_string(&self.known_entities.properties.sha_256, sha));
         }
 
-        self.client
-            .create_object(ObjectType::Item, &label, claims)
+        self.store
+            .create_entity(ObjectType::Item, &label, claims)
             .context("impossible to insert data source")
     }
 
-    pub fn insert_route(
+    pub fn set_import_status(
         &self,
-        route: &gtfs_structures::Route,
         data_source_id: &str,
-        producer_name: &str,
+        status: ImportStatus,
+    ) -> Result<(), anyhow::Error> {
+        self.store
+            .add_claims(
+                data_source_id,
+                vec![claim_string(
+                    &self.known_entities.properties.import_status,
+                    status.as_str(),
+                )],
+            )
+            .with_context(|| {
+                format!(
+                    "impossible to set the status of the data source {}",
+                    data_source_id
+                )
+            })
+    }
+
+    /// Add the counts of the import to the data source
+    pub fn set_import_summary(
+        &self,
+        data_source_id: &str,
+        report: &ImportReport,
+    ) -> Result<(), anyhow::Error> {
+        let properties = &self.known_entities.properties;
+        let count = |property: &str, count: usize| claim_quantity(property, count as f64);
+        self.store
+            .add_claims(
+                data_source_id,
+                vec![
+                    count(
+                        &properties.route_count,
+                        report.counts(EntityKind::Route).imported(),
+                    ),
+                    count(
+                        &properties.stop_count,
+                        report.counts(EntityKind::Stop).imported(),
+                    ),
+                    count(&properties.relation_count, report.relations_added),
+                    count(&properties.warning_count, report.warnings.len()),
+                ],
+            )
+            .with_context(|| {
+                format!(
+                    "impossible to add the summary of the import to the data source {}",
+                    data_source_id
+                )
+            })
+    }
+
+    /// claims of a newly created entity, to know in which data source it has been seen
+    fn provenance_claims(&self, data_source_id: &str) -> Vec<Option<serde_json::Value>> {
+        vec![
+            claim_item(
+                &self.known_entities.properties.first_seen_in,
+                data_source_id,
+            ),
+            claim_item(&self.known_entities.properties.seen_in, data_source_id),
+        ]
+    }
+
+    pub fn insert_agency(
+        &self,
+        agency: &gtfs_structures::Agency,
+        data_source_id: &str,
     ) -> Result<String, anyhow::Error> {
-        let route_name = if !route.long_name.is_empty() {
-            route.long_name.as_str()
-        } else {
-            route.short_name.as_str()
-        };
+        let mut claims = vec![
+            claim_item(
+                &self.known_entities.properties.instance_of,
+                &self.known_entities.items.agency,
+            ),
+            claim_item(&self.known_entities.properties.data_source, data_source_id),
+            claim_string(&self.known_entities.properties.gtfs_name, &agency.name),
+            claim_string(&self.known_entities.properties.url, &agency.url),
+            claim_string(&self.known_entities.properties.timezone, &agency.timezone),
+        ];
+        if let Some(id) = &agency.id {
+            claims.push(claim_string(&self.known_entities.properties.gtfs_id, id));
+        }
+        if let Some(phone) = &agency.phone {
+            claims.push(claim_string(&self.known_entities.properties.phone, phone));
+        }
+        claims.extend(self.provenance_claims(data_source_id));
 
-        let label = format!("{:?} {} ({})", route.route_type, route_name, producer_name);
-        let claims = vec![
+        self.store
+            .create_entity(ObjectType::Item, &agency.name, claims)
+            .context("impossible to insert agency")
+    }
+
+    /// claims of the route given by the GTFS
+    fn route_claims(
+        &self,
+        route: &gtfs_structures::Route,
+        details: Option<&RouteDetails>,
+    ) -> Vec<Option<serde_json::Value>> {
+        let mut claims = vec![
             claim_item(
                 &self.known_entities.properties.instance_of,
                 &self.known_entities.items.route,
             ),
             claim_string(&self.known_entities.properties.gtfs_id, &route.id),
-            claim_item(&self.known_entities.properties.data_source, data_source_id),
             claim_string(
                 &self.known_entities.properties.gtfs_short_name,
                 &route.short_name,
@@ -76,55 +253,322 @@ impl TopoWriter {
                 self.known_entities.physical_mode(route),
             ),
         ];
+        if let Some(details) = details {
+            let optional_string = |prop: &str, value: &Option<String>| {
+                value.as_ref().and_then(|v| claim_string(prop, v))
+            };
+            claims.extend(vec![
+                optional_string(&self.known_entities.properties.color, &details.route_color),
+                optional_string(
+                    &self.known_entities.properties.text_color,
+                    &details.route_text_color,
+                ),
+                optional_string(&self.known_entities.properties.url, &details.route_url),
+                optional_string(
+                    &self.known_entities.properties.gtfs_description,
+                    &details.route_desc,
+                ),
+                details.route_sort_order.and_then(|o| {
+                    claim_quantity(&self.known_entities.properties.sort_order, f64::from(o))
+                }),
+            ]);
+        }
+        claims
+    }
+
+    pub fn insert_route(
+        &self,
+        route: &gtfs_structures::Route,
+        details: Option<&RouteDetails>,
+        data_source_id: &str,
+        producer_name: &str,
+    ) -> Result<String, anyhow::Error> {
+        let label = route_label(route, producer_name);
+        let mut claims = self.route_claims(route, details);
+        claims.push(claim_item(
+            &self.known_entities.properties.data_source,
+            data_source_id,
+        ));
+        claims.extend(self.provenance_claims(data_source_id));
 
-        self.client
-            .create_object(ObjectType::Item, &label, claims)
+        self.store
+            .create_entity(ObjectType::Item, &label, claims)
             .context("impossible to insert route")
     }
 
+    /// properties of the routes whose values are given by the GTFS.
+    /// The data source is not updated, it is the one the route comes from
+    fn route_properties(&self) -> Vec<&str> {
+        let properties = &self.known_entities.properties;
+        vec![
+            properties.instance_of.as_str(),
+            properties.gtfs_id.as_str(),
+            properties.gtfs_short_name.as_str(),
+            properties.gtfs_long_name.as_str(),
+            properties.has_physical_mode.as_str(),
+            properties.color.as_str(),
+            properties.text_color.as_str(),
+            properties.url.as_str(),
+            properties.gtfs_description.as_str(),
+            properties.sort_order.as_str(),
+        ]
+    }
+
+    /// Update the label and the claims of the route given by the GTFS.
+    /// The previous label is kept as an alias, and the other claims are kept
+    pub fn update_route(
+        &self,
+        route_id: &str,
+        route: &gtfs_structures::Route,
+        details: Option<&RouteDetails>,
+        producer_name: &str,
+    ) -> Result<(), anyhow::Error> {
+        let entity = self.store.get_entity(route_id)?;
+        let label = route_label(route, producer_name);
+        if entity.label != label {
+            log::info!(
+                "renaming route {} from “{}” to “{}”",
+                route_id,
+                entity.label,
+                label
+            );
+            self.store
+                .set_label(route_id, &label, Some(&entity.label))
+                .context("impossible to rename route")?;
+        }
+        let claims = self.route_claims(route, details);
+
+        self.update_claims(&entity, &self.route_properties(), claims)
+            .context("impossible to update route")
+    }
+
+    pub fn insert_level(
+        &self,
+        level: &crate::gtfs_extra::Level,
+        data_source_id: &str,
+    ) -> Result<String, anyhow::Error> {
+        let name = level.level_name.as_deref().unwrap_or(&level.level_id);
+        let mut claims = vec![
+            claim_item(
+                &self.known_entities.properties.instance_of,
+                &self.known_entities.items.level,
+            ),
+            claim_string(&self.known_entities.properties.gtfs_id, &level.level_id),
+            claim_item(&self.known_entities.properties.data_source, data_source_id),
+            claim_string(&self.known_entities.properties.gtfs_name, name),
+            claim_quantity(
+                &self.known_entities.properties.level_index,
+                level.level_index,
+            ),
+        ];
+        claims.extend(self.provenance_claims(data_source_id));
+
+        self.store
+            .create_entity(ObjectType::Item, name, claims)
+            .context("impossible to insert level")
+    }
+
+    /// Insert a route pattern, linked to its route and to its stops.
+    /// `stop_ids` are the wikibase ids of the stops of the pattern, in order
+    pub fn insert_route_pattern(
+        &self,
+        pattern: &RoutePattern,
+        label: &str,
+        route_id: &str,
+        stop_ids: &[&String],
+        data_source_id: &str,
+    ) -> Result<String, anyhow::Error> {
+        let properties = &self.known_entities.properties;
+        let mut claims = vec![
+            claim_item(
+                &properties.instance_of,
+                &self.known_entities.items.route_pattern,
+            ),
+            claim_string(&properties.gtfs_id, &pattern.gtfs_id()),
+            claim_item(&properties.data_source, data_source_id),
+            claim_item(&properties.pattern_of, route_id),
+        ];
+        if let Some(direction_id) = &pattern.direction_id {
+            claims.push(claim_string(&properties.gtfs_direction_id, direction_id));
+        }
+        if let Some(headsign) = &pattern.headsign {
+            claims.push(claim_string(&properties.gtfs_headsign, headsign));
+        }
+        // a stop can be served several times by a pattern, so each claim has its position
+        claims.extend(stop_ids.iter().enumerate().map(|(i, stop_id)| {
+            with_qualifiers(
+                claim_item(&properties.has_stop, stop_id),
+                vec![claim_quantity(&properties.stop_sequence, (i + 1) as f64)],
+            )
+        }));
+        claims.extend(self.provenance_claims(data_source_id));
+
+        self.store
+            .create_entity(ObjectType::Item, label, claims)
+            .context("impossible to insert route pattern")
+    }
+
+    /// claims of the stop given by the GTFS
     fn stop_claims(
         &self,
         stop: &gtfs_structures::Stop,
-        data_source_id: &str,
+        details: Option<&StopDetails>,
     ) -> Vec<Option<serde_json::Value>> {
-        vec![
+        let mut claims = vec![
             claim_item(
                 &self.known_entities.properties.instance_of,
                 &self.known_entities.location_type(stop),
             ),
             claim_string(&self.known_entities.properties.gtfs_id, &stop.id),
-            claim_item(&self.known_entities.properties.data_source, data_source_id),
             claim_string(&self.known_entities.properties.gtfs_name, &stop.name),
             claim_coord(
                 &self.known_entities.properties.coordinate_location,
                 stop.longitude,
                 stop.latitude,
             ),
-        ]
+        ];
+        if let Some(details) = details {
+            let properties = &self.known_entities.properties;
+            let optional_string = |prop: &str, value: &Option<String>| {
+                value.as_ref().and_then(|v| claim_string(prop, v))
+            };
+            claims.extend(vec![
+                optional_string(&properties.gtfs_code, &details.stop_code),
+                optional_string(&properties.platform_code, &details.platform_code),
+                optional_string(&properties.gtfs_zone_id, &details.zone_id),
+                optional_string(&properties.gtfs_description, &details.stop_desc),
+                optional_string(&properties.url, &details.stop_url),
+                optional_string(&properties.timezone, &details.stop_timezone),
+                details
+                    .wheelchair_boarding
+                    .and_then(|w| self.known_entities.wheelchair_boarding(w))
+                    .and_then(|item| claim_item(&properties.wheelchair_boarding, item)),
+            ]);
+        }
+        claims
     }
 
     pub fn insert_stop(
         &self,
         stop: &gtfs_structures::Stop,
+        details: Option<&StopDetails>,
         data_source_id: &str,
     ) -> Result<String, anyhow::Error> {
-        let claims = self.stop_claims(stop, data_source_id);
+        let mut claims = self.stop_claims(stop, details);
+        claims.push(claim_item(
+            &self.known_entities.properties.data_source,
+            data_source_id,
+        ));
+        claims.extend(self.provenance_claims(data_source_id));
 
-        self.client
-            .create_object(ObjectType::Item, &stop.name, claims)
+        self.store
+            .create_entity(ObjectType::Item, &stop.name, claims)
             .context("impossible to insert stop")
     }
 
+    /// properties of the stops whose values are given by the GTFS.
+    /// The data source is not updated, it is the one the stop comes from
+    fn stop_properties(&self) -> Vec<&str> {
+        let properties = &self.known_entities.properties;
+        vec![
+            properties.instance_of.as_str(),
+            properties.gtfs_id.as_str(),
+            properties.gtfs_name.as_str(),
+            properties.coordinate_location.as_str(),
+            properties.gtfs_code.as_str(),
+            properties.platform_code.as_str(),
+            properties.gtfs_zone_id.as_str(),
+            properties.gtfs_description.as_str(),
+            properties.url.as_str(),
+            properties.timezone.as_str(),
+            properties.wheelchair_boarding.as_str(),
+        ]
+    }
+
+    /// Update the claims of the stop given by the GTFS.
+    /// The other claims (relations, claims added by hand, ...) are kept
     pub fn update_stop(
         &self,
         stop_id: &str,
         stop: &gtfs_structures::Stop,
-        data_source_id: &str,
+        details: Option<&StopDetails>,
     ) -> Result<(), anyhow::Error> {
-        let claims = self.stop_claims(stop, data_source_id);
+        let claims = self.stop_claims(stop, details);
+        let entity = self.store.get_entity(stop_id)?;
 
-        self.client
-            .override_object_claims(stop_id, claims)
+        self.update_claims(&entity, &self.stop_properties(), claims)
             .context("impossible to update stop")
     }
+
+    /// Change the values of the given properties of an existing entity to the values of the claims.
+    /// Only the values that changed are removed or added, the other properties are not modified
+    fn update_claims(
+        &self,
+        entity: &Entity,
+        properties: &[&str],
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<(), anyhow::Error> {
+        let entity_id = entity.id.as_str();
+        let claims: Vec<_> = claims.into_iter().flatten().collect();
+        let values: Vec<_> = claims
+            .iter()
+            .filter_map(PropertyValue::from_claim)
+            .collect();
+
+        let mut removed = vec![];
+        for property in properties {
+            for value in entity.properties.get(*property).into_iter().flatten() {
+                if !values
+                    .iter()
+                    .any(|(p, v)| p == property && v.is_same(value))
+                {
+                    removed.push(value.to_claim(property));
+                }
+            }
+        }
+        let added: Vec<_> = claims
+            .into_iter()
+            .filter(|claim| match PropertyValue::from_claim(claim) {
+                Some((property, value)) => !entity
+                    .properties
+                    .get(&property)
+                    .map_or(false, |existing| existing.iter().any(|e| e.is_same(&value))),
+                None => true,
+            })
+            .map(Some)
+            .collect();
+
+        if !removed.is_empty() {
+            log::debug!("removing {} claims of {}", removed.len(), entity_id);
+            self.store.remove_claims(entity_id, removed)?;
+        }
+        if !added.is_empty() {
+            log::debug!("adding {} claims to {}", added.len(), entity_id);
+            self.store.add_claims(entity_id, added)?;
+        }
+        Ok(())
+    }
+
+    /// add all the claims of the batch, with one api call by entity.
+    /// The entities are edited concurrently by the workers of the pool,
+    /// `on_added` is called after the edit of each entity
+    pub fn add_claims_batch(
+        &self,
+        batch: ClaimsBatch,
+        pool: &rayon::ThreadPool,
+        on_added: impl Fn(&str) -> Result<(), anyhow::Error> + Sync + Send,
+    ) -> Result<(), anyhow::Error> {
+        log::info!("adding claims to {} entities", batch.claims.len());
+        pool.install(|| {
+            batch
+                .claims
+                .into_par_iter()
+                .try_for_each(|(entity_id, claims)| {
+                    self.store
+                        .add_claims(&entity_id, claims)
+                        .with_context(|| format!("impossible to add claims to {}", entity_id))?;
+                    on_added(&entity_id)
+                })
+        })
+    }
 }
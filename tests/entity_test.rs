@@ -0,0 +1,14 @@
+use transit_topo::entity::PropertyValue;
+
+#[test]
+fn compare_coordinates_at_their_precision() {
+    let coord = |latitude, longitude| PropertyValue::Coord {
+        latitude,
+        longitude,
+    };
+    // the coordinates returned by wikibase are rounded
+    assert!(coord(36.425_288_4, -117.133_162_3).is_same(&coord(36.425_288, -117.133_162)));
+    assert!(!coord(36.425_288, -117.133_162).is_same(&coord(36.425_298, -117.133_162)));
+    assert!(PropertyValue::String("a".to_owned()).is_same(&PropertyValue::String("a".to_owned())));
+    assert!(!PropertyValue::Item("Q1".to_owned()).is_same(&PropertyValue::Item("Q2".to_owned())));
+}
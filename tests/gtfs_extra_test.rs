@@ -0,0 +1,36 @@
+use transit_topo::gtfs_extra;
+
+#[test]
+fn ignore_invalid_optional_fields() {
+    let gtfs = std::env::temp_dir().join(format!("topo_gtfs_{}", std::process::id()));
+    std::fs::create_dir_all(&gtfs).unwrap();
+    std::fs::write(
+        gtfs.join("transfers.txt"),
+        "from_stop_id,to_stop_id,transfer_type,min_transfer_time\n\
+         A,B,2,3 minutes\n\
+         B,A,x,180\n",
+    )
+    .unwrap();
+    std::fs::write(
+        gtfs.join("routes.txt"),
+        "route_id,route_short_name,route_type,route_sort_order\n\
+         R1,1,3,first\n\
+         R2,2,3,2\n",
+    )
+    .unwrap();
+    let gtfs_path = gtfs.to_str().unwrap().to_owned();
+
+    let transfers = gtfs_extra::read_transfers(&gtfs_path).unwrap();
+    let routes = gtfs_extra::read_route_details(&gtfs_path).unwrap();
+    std::fs::remove_dir_all(&gtfs).unwrap();
+
+    // the lines are kept, without the invalid values
+    assert_eq!(transfers.len(), 2);
+    assert_eq!(transfers[0].transfer_type, Some(2));
+    assert_eq!(transfers[0].min_transfer_time, None);
+    assert_eq!(transfers[1].transfer_type, None);
+    assert_eq!(transfers[1].min_transfer_time, Some(180));
+    assert_eq!(routes.len(), 2);
+    assert_eq!(routes[0].route_sort_order, None);
+    assert_eq!(routes[1].route_sort_order, Some(2));
+}
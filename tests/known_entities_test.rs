@@ -0,0 +1,24 @@
+use transit_topo::known_entities::EntitiesId;
+
+#[test]
+fn replace_known_entities_in_claims() {
+    let mut entities = EntitiesId::default();
+    entities.properties.instance_of = "P3".to_owned();
+    entities.items.route = "Q1".to_owned();
+    entities.items.route_pattern = "Q2".to_owned();
+
+    let claims = entities.replace_known_entities(vec![
+        ("@instance_of".to_owned(), "@route_pattern".to_owned()),
+        ("@instance_of".to_owned(), "@route".to_owned()),
+        // the unknown references are kept
+        ("@unknown".to_owned(), "bob@example.com".to_owned()),
+    ]);
+    assert_eq!(
+        claims,
+        vec![
+            ("P3".to_owned(), "wd:Q2".to_owned()),
+            ("P3".to_owned(), "wd:Q1".to_owned()),
+            ("@unknown".to_owned(), "bob@example.com".to_owned()),
+        ]
+    );
+}
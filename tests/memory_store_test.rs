@@ -0,0 +1,1226 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use transit_topo::clients::api_client::{
+    claim_coord, claim_item, claim_quantity, claim_string, with_qualifiers,
+};
+use transit_topo::entity::Entity;
+use transit_topo::gtfs_extra;
+use transit_topo::import_report::{Action, EntityKind, ImportReport};
+use transit_topo::importer::ImportOptions;
+use transit_topo::known_entities::EntitiesId;
+use transit_topo::store::{resolve_placeholders, MemoryStore, QuickStatementsStore, Store};
+use transit_topo::topo_query::ProducerEntity;
+use transit_topo::topo_writer::ClaimsBatch;
+use transit_topo::{GtfsImporter, ObjectType};
+
+fn gtfs() -> String {
+    format!("{}/tests/fixtures/gtfs.zip", env!("CARGO_MANIFEST_DIR"))
+}
+
+fn create_producer(store: &MemoryStore, label: &str) -> String {
+    let known_entities = store.known_entities();
+    store
+        .create_entity(
+            ObjectType::Item,
+            label,
+            vec![claim_item(
+                &known_entities.properties.instance_of,
+                &known_entities.items.producer,
+            )],
+        )
+        .expect("impossible to create producer")
+}
+
+#[test]
+fn import_gtfs_in_memory() {
+    let store = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&store, "bob the bus mapper");
+    let producer_name = store
+        .find_producer(&producer_id)
+        .unwrap()
+        .expect("no producer");
+    let properties = store.known_entities().properties.clone();
+    let items = store.known_entities().items.clone();
+
+    let importer = GtfsImporter::new(store.clone()).unwrap();
+    let plan = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            &producer_name,
+            &ImportOptions::default(),
+        )
+        .expect("impossible to import");
+    assert!(!plan.already_imported);
+    assert_eq!(plan.counts(EntityKind::Route).created, 5);
+    assert_eq!(plan.counts(EntityKind::Stop).created, 9);
+    assert_eq!(plan.counts(EntityKind::RoutePattern).created, 9);
+    assert_eq!(plan.relations_added, plan.links.len());
+    assert!(plan.errors.is_empty());
+    assert!(plan.timings.iter().any(|t| t.phase == "stops"));
+
+    let data_source = store.get_entity(&plan.data_source).unwrap();
+    assert_eq!(
+        data_source.properties[&properties.import_status][0].value(),
+        "completed"
+    );
+    assert_eq!(
+        data_source.properties[&properties.route_count][0].value(),
+        "+5"
+    );
+    assert_eq!(
+        data_source.properties[&properties.stop_count][0].value(),
+        "+9"
+    );
+
+    let mut types = store.known_entities().stop_types();
+    types.push(&items.route);
+    types.push(&items.level);
+    types.push(&items.agency);
+    let entities = store.find_producer_entities(&producer_id, &types).unwrap();
+    assert_eq!(entities.len(), 16);
+
+    let ab = store
+        .find_route(&producer_id, "AB")
+        .unwrap()
+        .expect("impossible to find AB");
+    let raw_ab = store.get_entity(&ab).unwrap();
+    assert_eq!(raw_ab.label, "Bus Airport - Bullfrog (bob the bus mapper)");
+    assert_eq!(
+        raw_ab.properties[&properties.first_seen_in][0].value(),
+        plan.data_source
+    );
+    let agency = entities
+        .iter()
+        .find(|e| e.gtfs_id == "DTA")
+        .expect("impossible to find DTA");
+    assert_eq!(
+        raw_ab.properties[&properties.operated_by][0].value(),
+        agency.id
+    );
+
+    // each direction of AB is a route pattern, with its ordered stops
+    let patterns = store
+        .find_producer_entities(&producer_id, &[&items.route_pattern])
+        .unwrap();
+    assert_eq!(patterns.len(), 9);
+    let ab_patterns: Vec<_> = patterns
+        .iter()
+        .filter(|p| p.gtfs_id.starts_with("AB:"))
+        .map(|p| store.get_entity(&p.id).unwrap())
+        .collect();
+    assert_eq!(ab_patterns.len(), 2);
+    let to_bullfrog = ab_patterns
+        .iter()
+        .find(|p| p.properties[&properties.gtfs_direction_id][0].value() == "0")
+        .expect("impossible to find the pattern of direction 0");
+    assert_eq!(
+        to_bullfrog.label,
+        "Bus Airport - Bullfrog (bob the bus mapper): Nye County Airport (Demo) → Bullfrog (Demo)"
+    );
+    assert_eq!(
+        to_bullfrog.properties[&properties.gtfs_headsign][0].value(),
+        "to Bullfrog"
+    );
+    assert_eq!(
+        to_bullfrog.properties[&properties.pattern_of][0].value(),
+        ab
+    );
+    let stop_id = |gtfs_id: &str| {
+        entities
+            .iter()
+            .find(|e| e.gtfs_id == gtfs_id)
+            .map(|e| e.id.clone())
+            .unwrap()
+    };
+    assert_eq!(
+        to_bullfrog.properties[&properties.has_stop]
+            .iter()
+            .map(|s| s.value().to_owned())
+            .collect::<Vec<_>>(),
+        vec![stop_id("BEATTY_AIRPORT"), stop_id("BULLFROG")]
+    );
+
+    // the stop "STAGECOACH" is part of 2 routes
+    let stage_coach = entities
+        .iter()
+        .find(|e| e.gtfs_id == "STAGECOACH")
+        .expect("impossible to find STAGECOACH");
+    let routes = store.get_entity(&stage_coach.id).unwrap().properties[&properties.part_of]
+        .iter()
+        .map(|r| store.get_entity(r.value()).unwrap().label)
+        .collect::<BTreeSet<_>>();
+    assert_eq!(
+        routes,
+        vec![
+            "Bus City (bob the bus mapper)".to_owned(),
+            "Bus Stagecoach - Airport Shuttle (bob the bus mapper)".to_owned(),
+        ]
+        .into_iter()
+        .collect()
+    );
+
+    // the same file is not imported twice
+    let plan = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            &producer_name,
+            &ImportOptions::default(),
+        )
+        .unwrap();
+    assert!(plan.already_imported);
+
+    // when forced, the existing entities are reused
+    let plan = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            &producer_name,
+            &ImportOptions {
+                force: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(!plan.already_imported);
+    assert!(plan.entities.iter().all(|e| e.action == Action::Skip));
+}
+
+/// Apply QuickStatements V1 commands to the store, like QuickStatements would,
+/// and return the ids of the created items in the order of their `CREATE`
+fn apply_quick_statements(store: &MemoryStore, statements: &str) -> Vec<String> {
+    let unquote = |value: &str| value.trim_matches('"').to_owned();
+    let claim = |property: &str, value: &str| {
+        if value.starts_with('"') {
+            claim_string(property, &unquote(value))
+        } else if let Some(coord) = value.strip_prefix('@') {
+            let mut coord = coord.split('/').map(|c| c.parse::<f64>().unwrap());
+            let lat = coord.next().unwrap();
+            let lon = coord.next().unwrap();
+            claim_coord(property, lon, lat)
+        } else if value.starts_with('Q') {
+            claim_item(property, value)
+        } else {
+            claim_quantity(property, value.parse().unwrap())
+        }
+    };
+    let mut created = vec![];
+    for line in statements.lines() {
+        if line == "CREATE" {
+            created.push(store.create_entity(ObjectType::Item, "", vec![]).unwrap());
+            continue;
+        }
+        let fields: Vec<_> = line.split('\t').collect();
+        assert!(
+            !fields
+                .iter()
+                .any(|f| f.trim_start_matches('-').starts_with("NEW")),
+            "unresolved placeholder in {}",
+            line
+        );
+        let (entity, remove) = match fields[0] {
+            "LAST" => (created.last().expect("LAST without CREATE").clone(), false),
+            id if id.starts_with('-') => (id[1..].to_owned(), true),
+            id => (id.to_owned(), false),
+        };
+        let applied = match fields[1] {
+            "Len" => store.set_label(&entity, &unquote(fields[2]), None),
+            "Aen" => {
+                let label = store.get_entity(&entity).unwrap().label;
+                store.set_label(&entity, &label, Some(&unquote(fields[2])))
+            }
+            property => {
+                let qualifiers = fields[3..].chunks(2).map(|q| claim(q[0], q[1])).collect();
+                let claims = vec![with_qualifiers(claim(property, fields[2]), qualifiers)];
+                if remove {
+                    store.remove_claims(&entity, claims)
+                } else {
+                    store.add_claims(&entity, claims)
+                }
+            }
+        };
+        applied.unwrap_or_else(|e| panic!("impossible to apply {}: {}", line, e));
+    }
+    created
+}
+
+#[test]
+fn export_quick_statements() {
+    let reader = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&reader, "bob the bus mapper");
+    let output = std::env::temp_dir().join(format!("topo_{}.qs", std::process::id()));
+    let pending = std::env::temp_dir().join(format!("topo_{}.qs.pending", std::process::id()));
+    let store = Arc::new(QuickStatementsStore::new(
+        reader.clone(),
+        Box::new(std::fs::File::create(&output).unwrap()),
+        Box::new(std::fs::File::create(&pending).unwrap()),
+    ));
+    let properties = store.known_entities().properties.clone();
+    let items = store.known_entities().items.clone();
+
+    let importer = GtfsImporter::new(store.clone()).unwrap();
+    let plan = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .expect("impossible to export");
+    store.finish().unwrap();
+    let statements = std::fs::read_to_string(&output).unwrap();
+    let pending_statements = std::fs::read_to_string(&pending).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    std::fs::remove_file(&pending).unwrap();
+
+    // nothing has been written in the store
+    assert_eq!(reader.find_route(&producer_id, "AB").unwrap(), None);
+
+    // the data source is the first created item, then the 16 entities of the GTFS
+    // and the 9 route patterns, each in its block
+    assert_eq!(plan.data_source, "NEW1");
+    let lines: Vec<_> = statements.lines().collect();
+    assert_eq!(lines[0], "CREATE");
+    assert_eq!(lines.iter().filter(|l| **l == "CREATE").count(), 26);
+    assert!(lines
+        .iter()
+        .all(|l| *l == "CREATE" || l.starts_with("LAST\t")));
+    let produced_by = format!("LAST\t{}\t{}", properties.produced_by, producer_id);
+    assert!(lines.contains(&produced_by.as_str()));
+    assert!(lines.contains(&"LAST\tLen\t\"Bus Airport - Bullfrog (bob the bus mapper)\""));
+    // the import status is added at the end, in the block of the data source
+    let completed = format!("LAST\t{}\t\"completed\"", properties.import_status);
+    let completed_line = lines.iter().position(|l| *l == completed).unwrap();
+    assert!(!lines[..completed_line]
+        .iter()
+        .skip(1)
+        .any(|l| *l == "CREATE"));
+
+    // the relations between the created items use their placeholders
+    let ab = plan
+        .entities
+        .iter()
+        .find(|e| e.gtfs_id == "AB")
+        .expect("impossible to find AB");
+    assert!(ab.id.starts_with("NEW"));
+    assert!(pending_statements
+        .lines()
+        .any(|l| l.ends_with(&format!("\t{}\t{}", properties.part_of, ab.id))));
+
+    // once applied, the statements give the same entities as a direct import
+    let created_ids = apply_quick_statements(&reader, &statements);
+    assert_eq!(created_ids.len(), 26);
+    let resolved = resolve_placeholders(&pending_statements, &created_ids).unwrap();
+    apply_quick_statements(&reader, &resolved);
+
+    let data_source = reader.get_entity(&created_ids[0]).unwrap();
+    assert_eq!(
+        data_source.properties[&properties.import_status][0].value(),
+        "completed"
+    );
+    let mut types = reader.known_entities().stop_types();
+    types.push(&items.route);
+    types.push(&items.level);
+    types.push(&items.agency);
+    let entities = reader.find_producer_entities(&producer_id, &types).unwrap();
+    assert_eq!(entities.len(), 16);
+    let ab = reader
+        .find_route(&producer_id, "AB")
+        .unwrap()
+        .expect("impossible to find AB");
+    let raw_ab = reader.get_entity(&ab).unwrap();
+    assert_eq!(raw_ab.label, "Bus Airport - Bullfrog (bob the bus mapper)");
+    assert_eq!(
+        raw_ab.properties[&properties.first_seen_in][0].value(),
+        created_ids[0]
+    );
+    // the stops of the route patterns are ordered
+    let stop_id = |gtfs_id: &str| {
+        entities
+            .iter()
+            .find(|e| e.gtfs_id == gtfs_id)
+            .map(|e| e.id.clone())
+            .unwrap()
+    };
+    let to_bullfrog = reader
+        .find_producer_entities(&producer_id, &[&items.route_pattern])
+        .unwrap()
+        .iter()
+        .map(|p| reader.get_entity(&p.id).unwrap())
+        .find(|p| {
+            p.properties[&properties.pattern_of][0].value() == ab
+                && p.properties[&properties.gtfs_direction_id][0].value() == "0"
+        })
+        .expect("impossible to find the pattern of direction 0");
+    assert_eq!(
+        to_bullfrog.properties[&properties.has_stop]
+            .iter()
+            .map(|s| s.value().to_owned())
+            .collect::<Vec<_>>(),
+        vec![stop_id("BEATTY_AIRPORT"), stop_id("BULLFROG")]
+    );
+    let stage_coach = reader.get_entity(&stop_id("STAGECOACH")).unwrap();
+    assert_eq!(stage_coach.properties[&properties.part_of].len(), 2);
+
+    // the file has been imported
+    let importer = GtfsImporter::new(reader.clone()).unwrap();
+    let plan = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .unwrap();
+    assert!(plan.already_imported);
+}
+
+#[test]
+fn quick_statements_normalize_unescapable_texts() {
+    let reader = Arc::new(MemoryStore::new());
+    let output = std::env::temp_dir().join(format!("topo_texts_{}.qs", std::process::id()));
+    let store = QuickStatementsStore::new(
+        reader.clone(),
+        Box::new(std::fs::File::create(&output).unwrap()),
+        Box::new(std::io::sink()),
+    );
+    let properties = reader.known_entities().properties.clone();
+    let id = store
+        .create_entity(ObjectType::Item, "bob \"the\" mapper", vec![])
+        .unwrap();
+    store
+        .add_claims(
+            &id,
+            vec![claim_string(
+                &properties.gtfs_short_name,
+                "bob\tthe\nmapper",
+            )],
+        )
+        .unwrap();
+    store.finish().unwrap();
+    let statements = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+
+    assert_eq!(
+        statements,
+        format!(
+            "CREATE\nLAST\tLen\t\"bob 'the' mapper\"\nLAST\t{}\t\"bob the mapper\"\n",
+            properties.gtfs_short_name
+        )
+    );
+}
+
+#[test]
+fn resolve_quick_statements_placeholders() {
+    let ids = vec!["Q12".to_owned(), "Q13".to_owned()];
+    assert_eq!(
+        resolve_placeholders("NEW1\tP5\tNEW2\tP6\t\"NEW2\"\n-NEW2\tP7\tQ3\n", &ids).unwrap(),
+        "Q12\tP5\tQ13\tP6\t\"NEW2\"\n-Q13\tP7\tQ3\n"
+    );
+    assert!(resolve_placeholders("NEW3\tP5\tQ1\n", &ids).is_err());
+}
+
+#[test]
+fn import_again_without_duplicating_relations() {
+    let store = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&store, "bob the bus mapper");
+    let properties = store.known_entities().properties.clone();
+    let items = store.known_entities().items.clone();
+    let relations = [
+        &properties.part_of,
+        &properties.operated_by,
+        &properties.transfer_to,
+        &properties.on_level,
+        &properties.pathway_to,
+        &properties.has_stop,
+    ];
+    // number of claims of each relation, by entity
+    let count_relations = || {
+        let mut types = store.known_entities().stop_types();
+        types.push(&items.route);
+        types.push(&items.level);
+        types.push(&items.agency);
+        types.push(&items.route_pattern);
+        let mut counts = BTreeMap::new();
+        for entity in store.find_producer_entities(&producer_id, &types).unwrap() {
+            let raw = store.get_entity(&entity.id).unwrap();
+            for property in &relations {
+                let count = raw.properties.get(*property).map_or(0, |v| v.len());
+                counts.insert((entity.id.clone(), property.to_string()), count);
+            }
+        }
+        counts
+    };
+
+    let importer = GtfsImporter::new(store.clone()).unwrap();
+    importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .unwrap();
+    let counts = count_relations();
+    assert!(counts.values().any(|c| *c > 0));
+
+    let plan = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions {
+                force: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(count_relations(), counts);
+    // only the seen in claims are added
+    assert!(plan.links.iter().all(|l| l.property == properties.seen_in));
+}
+
+#[test]
+fn import_transfers() {
+    let store = Arc::new(MemoryStore::new());
+    let properties = store.known_entities().properties.clone();
+    let importer = GtfsImporter::new(store.clone()).unwrap();
+    let transfer = |from: &str, to: &str, transfer_type| gtfs_extra::Transfer {
+        from_stop_id: from.to_owned(),
+        to_stop_id: to.to_owned(),
+        transfer_type,
+        min_transfer_time: None,
+    };
+    let stop_mapping = vec![("A", "Q10"), ("B", "Q11"), ("C", "Q12")]
+        .into_iter()
+        .map(|(gtfs_id, id)| (gtfs_id.to_owned(), id.to_owned()))
+        .collect();
+    let mut claims = ClaimsBatch::default();
+    let mut report = ImportReport::default();
+    importer.insert_transfer_relations(
+        &[
+            transfer("A", "B", Some(1)),
+            // no transfer type, or an invalid one
+            transfer("B", "C", None),
+            // the transfer is not possible
+            transfer("C", "A", Some(3)),
+        ],
+        &stop_mapping,
+        &mut claims,
+        &mut report,
+    );
+
+    let claims: Vec<_> = claims.iter().collect();
+    assert_eq!(claims.len(), 2);
+    assert_eq!(claims[0].0, "Q10");
+    assert_eq!(
+        claims[0].1["qualifiers"][&properties.gtfs_transfer_type][0]["datavalue"]["value"],
+        "1"
+    );
+    assert_eq!(claims[1].0, "Q11");
+    assert!(claims[1].1["qualifiers"]
+        .get(&properties.gtfs_transfer_type)
+        .is_none());
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let store = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&store, "bob the bus mapper");
+    let items = store.known_entities().items.clone();
+    let mut types = store.known_entities().stop_types();
+    types.push(&items.route);
+    types.push(&items.level);
+    types.push(&items.agency);
+    types.push(&items.route_pattern);
+    // the labels and the claims of all the entities of the producer
+    let snapshot = || {
+        store
+            .find_producer_entities(&producer_id, &types)
+            .unwrap()
+            .into_iter()
+            .map(|e| {
+                let entity = store.get_entity(&e.id).unwrap();
+                (e.id, (entity.label, entity.aliases, entity.properties))
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+    // the next id given by the store changes if anything has been created
+    let next_id = || {
+        store
+            .create_entity(ObjectType::Item, "probe", vec![])
+            .unwrap()
+    };
+    let count_actions = |plan: &serde_json::Value, kind: &str, action: &str| {
+        plan["entities"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["kind"] == kind && e["action"] == action)
+            .count()
+    };
+
+    let dry_run = GtfsImporter::new_dry_run(store.clone()).unwrap();
+    let probe = next_id();
+    let plan = dry_run
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .unwrap();
+    assert!(plan.dry_run);
+    assert!(snapshot().is_empty());
+    assert_eq!(
+        next_id(),
+        format!("Q{}", probe[1..].parse::<usize>().unwrap() + 1)
+    );
+    let json = serde_json::to_value(&plan).unwrap();
+    assert_eq!(count_actions(&json, "route", "create"), 5);
+    assert_eq!(count_actions(&json, "stop", "create"), 9);
+    assert_eq!(count_actions(&json, "route_pattern", "create"), 9);
+    assert!(plan.summary().contains("claims planned"));
+
+    GtfsImporter::new(store.clone())
+        .unwrap()
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .unwrap();
+    let before = snapshot();
+    let probe = next_id();
+    let plan = dry_run
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions {
+                force: true,
+                override_existing: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(snapshot(), before);
+    assert_eq!(
+        next_id(),
+        format!("Q{}", probe[1..].parse::<usize>().unwrap() + 1)
+    );
+    let json = serde_json::to_value(&plan).unwrap();
+    assert_eq!(count_actions(&json, "route", "update"), 5);
+    assert_eq!(count_actions(&json, "stop", "update"), 9);
+    assert_eq!(count_actions(&json, "route", "create"), 0);
+    // the existing entities are planned to be seen in the new data source
+    assert!(!json["links"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn update_stops_without_removing_other_claims() {
+    let store = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&store, "bob the bus mapper");
+    let properties = store.known_entities().properties.clone();
+
+    let importer = GtfsImporter::new(store.clone()).unwrap();
+    let first_plan = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .unwrap();
+    let stage_coach = first_plan
+        .entities
+        .iter()
+        .find(|e| e.gtfs_id == "STAGECOACH")
+        .expect("impossible to find STAGECOACH")
+        .id
+        .clone();
+
+    // a curator adds a claim, and an outdated name is left on the stop
+    store
+        .add_claims(
+            &stage_coach,
+            vec![
+                claim_string(&properties.source, "checked on the ground"),
+                claim_string(&properties.gtfs_name, "Stagecoach (old name)"),
+            ],
+        )
+        .unwrap();
+
+    let plan = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions {
+                force: true,
+                override_existing: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(plan
+        .entities
+        .iter()
+        .any(|e| e.id == stage_coach && e.action == Action::Update));
+
+    let stop = store.get_entity(&stage_coach).unwrap();
+    let values = |property: &str| -> Vec<String> {
+        stop.properties
+            .get(property)
+            .into_iter()
+            .flatten()
+            .map(|v| v.value().to_owned())
+            .collect()
+    };
+    // the values given by the GTFS are updated
+    assert_eq!(
+        values(&properties.gtfs_name),
+        vec!["Stagecoach Hotel & Casino (Demo)".to_owned()]
+    );
+    // the data source is still the one the stop comes from, the new one is in seen in
+    assert_eq!(
+        values(&properties.data_source),
+        vec![first_plan.data_source.clone()]
+    );
+    assert!(values(&properties.seen_in).contains(&plan.data_source));
+    // and the other claims are kept
+    assert_eq!(
+        values(&properties.source),
+        vec!["checked on the ground".to_owned()]
+    );
+    assert_eq!(
+        values(&properties.part_of)
+            .into_iter()
+            .collect::<BTreeSet<_>>()
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn update_renamed_routes() {
+    let store = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&store, "bob the bus mapper");
+    let properties = store.known_entities().properties.clone();
+
+    let importer = GtfsImporter::new(store.clone()).unwrap();
+    importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .unwrap();
+    let ab = store
+        .find_route(&producer_id, "AB")
+        .unwrap()
+        .expect("impossible to find AB");
+
+    // the route had another name in a previous GTFS, and a curator added a claim
+    store
+        .set_label(&ab, "Bus Airport (bob the bus mapper)", None)
+        .unwrap();
+    store
+        .add_claims(
+            &ab,
+            vec![
+                claim_string(&properties.gtfs_long_name, "Airport"),
+                claim_string(&properties.source, "checked on the ground"),
+            ],
+        )
+        .unwrap();
+
+    let plan = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions {
+                force: true,
+                override_existing: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(plan
+        .entities
+        .iter()
+        .any(|e| e.id == ab && e.action == Action::Update));
+
+    let route = store.get_entity(&ab).unwrap();
+    assert_eq!(route.label, "Bus Airport - Bullfrog (bob the bus mapper)");
+    assert_eq!(
+        route.aliases,
+        vec!["Bus Airport (bob the bus mapper)".to_owned()]
+    );
+    let values = |property: &str| -> Vec<String> {
+        route
+            .properties
+            .get(property)
+            .into_iter()
+            .flatten()
+            .map(|v| v.value().to_owned())
+            .collect()
+    };
+    assert_eq!(
+        values(&properties.gtfs_long_name),
+        vec!["Airport - Bullfrog".to_owned()]
+    );
+    assert_eq!(
+        values(&properties.source),
+        vec!["checked on the ground".to_owned()]
+    );
+}
+
+/// A store failing to create or update the entities whose label contains a text
+struct FailingStore {
+    inner: Arc<MemoryStore>,
+    failing_label: &'static str,
+}
+
+impl Store for FailingStore {
+    fn known_entities(&self) -> &EntitiesId {
+        self.inner.known_entities()
+    }
+    fn find_producer(&self, producer_id: &str) -> Result<Option<String>, anyhow::Error> {
+        self.inner.find_producer(producer_id)
+    }
+    fn find_route(
+        &self,
+        producer_id: &str,
+        gtfs_id: &str,
+    ) -> Result<Option<String>, anyhow::Error> {
+        self.inner.find_route(producer_id, gtfs_id)
+    }
+    fn find_stop(
+        &self,
+        producer_id: &str,
+        stop: &gtfs_structures::Stop,
+    ) -> Result<Option<String>, anyhow::Error> {
+        self.inner.find_stop(producer_id, stop)
+    }
+    fn find_agency(
+        &self,
+        producer_id: &str,
+        agency: &gtfs_structures::Agency,
+    ) -> Result<Option<String>, anyhow::Error> {
+        self.inner.find_agency(producer_id, agency)
+    }
+    fn find_producer_entities(
+        &self,
+        producer_id: &str,
+        types: &[&str],
+    ) -> Result<Vec<ProducerEntity>, anyhow::Error> {
+        self.inner.find_producer_entities(producer_id, types)
+    }
+    fn find_data_source_by_sha256(
+        &self,
+        producer_id: &str,
+        sha_256: &str,
+    ) -> Result<Option<String>, anyhow::Error> {
+        self.inner.find_data_source_by_sha256(producer_id, sha_256)
+    }
+    fn create_entity(
+        &self,
+        object_type: ObjectType,
+        label: &str,
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<String, anyhow::Error> {
+        if label.contains(self.failing_label) {
+            return Err(anyhow::anyhow!("invalid label {}", label));
+        }
+        self.inner.create_entity(object_type, label, claims)
+    }
+    fn add_claims(
+        &self,
+        entity_id: &str,
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<(), anyhow::Error> {
+        self.inner.add_claims(entity_id, claims)
+    }
+    fn remove_claims(
+        &self,
+        entity_id: &str,
+        claims: Vec<Option<serde_json::Value>>,
+    ) -> Result<(), anyhow::Error> {
+        if self
+            .inner
+            .get_entity(entity_id)?
+            .label
+            .contains(self.failing_label)
+        {
+            return Err(anyhow::anyhow!("invalid entity {}", entity_id));
+        }
+        self.inner.remove_claims(entity_id, claims)
+    }
+    fn set_label(
+        &self,
+        entity_id: &str,
+        label: &str,
+        alias: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        self.inner.set_label(entity_id, label, alias)
+    }
+    fn get_entity(&self, entity_id: &str) -> Result<Entity, anyhow::Error> {
+        self.inner.get_entity(entity_id)
+    }
+}
+
+#[test]
+fn keep_going_after_failed_entities() {
+    let inner = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&inner, "bob the bus mapper");
+    let store = Arc::new(FailingStore {
+        inner,
+        failing_label: "Stagecoach",
+    });
+    let properties = store.known_entities().properties.clone();
+
+    // by default the import stops at the first error
+    let importer = GtfsImporter::new(store.clone()).unwrap();
+    let failed = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .expect_err("the import should stop at the first error");
+    // the report of what has been done is returned with the error
+    assert_eq!(failed.report.producer, producer_id);
+    let data_source = store.get_entity(&failed.report.data_source).unwrap();
+    assert_eq!(
+        data_source.properties[&properties.import_status][0].value(),
+        "failed"
+    );
+
+    let mut importer = GtfsImporter::new(store.clone()).unwrap();
+    importer.keep_going = true;
+    let report = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions {
+                force: true,
+                ..Default::default()
+            },
+        )
+        .expect("the import should continue after the failures");
+
+    let failed: BTreeSet<_> = report
+        .failures
+        .iter()
+        .map(|f| (f.kind, f.gtfs_id.as_str()))
+        .collect();
+    assert_eq!(
+        failed,
+        vec![
+            (EntityKind::Route, "STBA"),
+            (EntityKind::Stop, "STAGECOACH")
+        ]
+        .into_iter()
+        .collect()
+    );
+    assert!(report.failures[0].error.contains("invalid label"));
+
+    // the other entities are imported, without relations to the failed ones
+    assert_eq!(report.counts(EntityKind::Route).imported(), 4);
+    assert_eq!(report.counts(EntityKind::Stop).imported(), 8);
+    // the patterns of CITY and STBA go through STAGECOACH
+    assert_eq!(report.counts(EntityKind::RoutePattern).imported(), 6);
+    assert!(report.entities.iter().all(|e| e.gtfs_id != "STAGECOACH"));
+    let city = store
+        .find_route(&producer_id, "CITY")
+        .unwrap()
+        .expect("impossible to find CITY");
+    assert!(report
+        .links
+        .iter()
+        .filter(|l| l.property == properties.part_of)
+        .all(|l| report.entities.iter().any(|e| e.id == l.entity)));
+    assert!(report.links.iter().any(|l| l.value["id"] == city.as_str()));
+
+    let data_source = store.get_entity(&report.data_source).unwrap();
+    assert_eq!(
+        data_source.properties[&properties.import_status][0].value(),
+        "failed"
+    );
+}
+
+#[test]
+fn resume_import_from_checkpoint() {
+    let store = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&store, "bob the bus mapper");
+    let properties = store.known_entities().properties.clone();
+    let items = store.known_entities().items.clone();
+
+    // state left by an import that crashed after creating the route AB
+    let data_source = store
+        .create_entity(
+            ObjectType::Item,
+            "interrupted data source",
+            vec![claim_item(&properties.produced_by, &producer_id)],
+        )
+        .unwrap();
+    let ab = store
+        .create_entity(
+            ObjectType::Item,
+            "AB created before the crash",
+            vec![
+                claim_item(&properties.instance_of, &items.route),
+                claim_string(&properties.gtfs_id, "AB"),
+                claim_item(&properties.data_source, &data_source),
+            ],
+        )
+        .unwrap();
+    let sha256 = transit_topo::gtfs_extra::sha256(&gtfs()).unwrap();
+    let path = std::env::temp_dir().join(format!("topo_checkpoint_{}.jsonl", std::process::id()));
+    std::fs::write(
+        &path,
+        format!(
+            concat!(
+                "{{\"start\":{{\"producer\":\"{}\",\"sha256\":\"{}\"}}}}\n",
+                "{{\"data_source\":\"{}\"}}\n",
+                "{{\"created\":{{\"kind\":\"route\",\"gtfs_id\":\"AB\",\"id\":\"{}\"}}}}\n",
+                // the crash happened while writing this line
+                "{{\"created\":{{\"kind\":\"route\",\"gtf"
+            ),
+            producer_id, sha256, data_source, ab
+        ),
+    )
+    .unwrap();
+
+    let importer = GtfsImporter::new(store.clone()).unwrap();
+    let report = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions {
+                checkpoint: Some(path.clone()),
+                ..Default::default()
+            },
+        )
+        .expect("impossible to resume the import");
+
+    // the import continued in the same data source, without creating AB again
+    assert_eq!(report.data_source, data_source);
+    let planned_ab = report
+        .entities
+        .iter()
+        .find(|e| e.kind == EntityKind::Route && e.gtfs_id == "AB")
+        .expect("impossible to find AB");
+    assert_eq!(planned_ab.id, ab);
+    assert_eq!(store.find_route(&producer_id, "AB").unwrap(), Some(ab));
+    let routes = store
+        .find_producer_entities(&producer_id, &[&items.route])
+        .unwrap();
+    assert_eq!(routes.len(), 5);
+    assert_eq!(
+        store.get_entity(&data_source).unwrap().properties[&properties.import_status][0].value(),
+        "completed"
+    );
+    // the checkpoint is removed once the import is completed
+    assert!(!path.exists());
+}
+
+#[test]
+fn import_again_after_failed_import() {
+    let inner = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&inner, "bob the bus mapper");
+    let properties = inner.known_entities().properties.clone();
+    let failing_store = Arc::new(FailingStore {
+        inner: inner.clone(),
+        failing_label: "Stagecoach",
+    });
+
+    let importer = GtfsImporter::new(failing_store).unwrap();
+    assert!(importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .is_err());
+
+    // the data source of the failed import does not prevent importing the same file again
+    let importer = GtfsImporter::new(inner.clone()).unwrap();
+    let report = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .expect("impossible to import again");
+    assert!(!report.already_imported);
+    assert!(report.failures.is_empty());
+    assert!(report
+        .entities
+        .iter()
+        .any(|e| e.gtfs_id == "STAGECOACH" && e.action == Action::Create));
+    let data_source = inner.get_entity(&report.data_source).unwrap();
+    assert_eq!(
+        data_source.properties[&properties.import_status][0].value(),
+        "completed"
+    );
+
+    // once completed, the file is not imported a third time
+    let report = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .unwrap();
+    assert!(report.already_imported);
+}
+
+#[test]
+fn deprecate_and_restore_missing_entities() {
+    let store = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&store, "bob the bus mapper");
+    let properties = store.known_entities().properties.clone();
+    let items = store.known_entities().items.clone();
+    let removed_in = |id: &str| -> Vec<String> {
+        store
+            .get_entity(id)
+            .unwrap()
+            .properties
+            .get(&properties.no_longer_present_since)
+            .into_iter()
+            .flatten()
+            .map(|v| v.value().to_owned())
+            .collect()
+    };
+
+    // a stop of a previous import, that is not in the GTFS anymore
+    let previous_data_source = store
+        .create_entity(
+            ObjectType::Item,
+            "previous data source",
+            vec![claim_item(&properties.produced_by, &producer_id)],
+        )
+        .unwrap();
+    let old_stop = store
+        .create_entity(
+            ObjectType::Item,
+            "old stop",
+            vec![
+                claim_item(&properties.instance_of, &items.stop_point),
+                claim_string(&properties.gtfs_id, "OLD"),
+                claim_item(&properties.data_source, &previous_data_source),
+            ],
+        )
+        .unwrap();
+
+    let importer = GtfsImporter::new(store.clone()).unwrap();
+    let options = ImportOptions {
+        deprecate_missing: true,
+        force: true,
+        ..Default::default()
+    };
+    let report = importer
+        .import_gtfs(&gtfs(), &producer_id, "bob the bus mapper", &options)
+        .unwrap();
+    let deprecated: Vec<_> = report
+        .entities
+        .iter()
+        .filter(|e| e.action == Action::Deprecate)
+        .map(|e| e.id.as_str())
+        .collect();
+    assert_eq!(deprecated, vec![old_stop.as_str()]);
+    assert_eq!(report.counts(EntityKind::Stop).deprecated, 1);
+    assert_eq!(removed_in(&old_stop), vec![report.data_source.clone()]);
+
+    // AB had been removed from the GTFS before coming back in this file
+    let ab = store.find_route(&producer_id, "AB").unwrap().unwrap();
+    store
+        .add_claims(
+            &ab,
+            vec![claim_item(
+                &properties.no_longer_present_since,
+                &previous_data_source,
+            )],
+        )
+        .unwrap();
+    let report = importer
+        .import_gtfs(&gtfs(), &producer_id, "bob the bus mapper", &options)
+        .unwrap();
+    assert!(removed_in(&ab).is_empty());
+    // the stop already deprecated is not deprecated again
+    assert!(report
+        .entities
+        .iter()
+        .all(|e| e.action != Action::Deprecate));
+    assert_eq!(removed_in(&old_stop).len(), 1);
+}
+
+#[test]
+fn keep_going_does_not_deprecate_failed_entities() {
+    let inner = Arc::new(MemoryStore::new());
+    let producer_id = create_producer(&inner, "bob the bus mapper");
+    let properties = inner.known_entities().properties.clone();
+    let importer = GtfsImporter::new(inner.clone()).unwrap();
+    importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions::default(),
+        )
+        .unwrap();
+
+    // the update of the stagecoach route and stop fails
+    let store = Arc::new(FailingStore {
+        inner: inner.clone(),
+        failing_label: "Stagecoach",
+    });
+    let mut importer = GtfsImporter::new(store).unwrap();
+    importer.keep_going = true;
+    let report = importer
+        .import_gtfs(
+            &gtfs(),
+            &producer_id,
+            "bob the bus mapper",
+            &ImportOptions {
+                override_existing: true,
+                deprecate_missing: true,
+                force: true,
+                ..Default::default()
+            },
+        )
+        .expect("the import should continue after the failures");
+    assert!(report
+        .failures
+        .iter()
+        .any(|f| f.kind == EntityKind::Stop && f.gtfs_id == "STAGECOACH"));
+
+    // neither the failed entities nor the patterns going through them are deprecated
+    assert!(report
+        .entities
+        .iter()
+        .all(|e| e.action != Action::Deprecate));
+    let items = inner.known_entities().items.clone();
+    let mut types = inner.known_entities().stop_types();
+    types.push(&items.route);
+    types.push(&items.route_pattern);
+    let entities = inner.find_producer_entities(&producer_id, &types).unwrap();
+    assert!(entities.iter().all(|e| !e.removed));
+    let data_source = inner.get_entity(&report.data_source).unwrap();
+    assert_eq!(
+        data_source.properties[&properties.import_status][0].value(),
+        "failed"
+    );
+}
@@ -24,8 +24,11 @@ fn check_initiale_state(wikibase: &utils::Wikibase) {
     assert_eq!(
         wikibase.get_topo_objects(),
         btreeset![
+            "accessible".to_owned(),
+            "agency".to_owned(),
             "bus".to_owned(),
             "cable_car".to_owned(),
+            "color".to_owned(),
             "connecting_line".to_owned(),
             "coordinate_location".to_owned(),
             "data_source".to_owned(),
@@ -34,49 +37,87 @@ fn check_initiale_state(wikibase: &utils::Wikibase) {
             "first_seen_in".to_owned(),
             "funicular".to_owned(),
             "gondola".to_owned(),
+            "gtfs_code".to_owned(),
+            "gtfs_description".to_owned(),
+            "gtfs_direction_id".to_owned(),
+            "gtfs_headsign".to_owned(),
             "gtfs_id".to_owned(),
+            "gtfs_is_bidirectional".to_owned(),
             "gtfs_long_name".to_owned(),
             "gtfs_name".to_owned(),
+            "gtfs_pathway_mode".to_owned(),
             "gtfs_short_name".to_owned(),
+            "gtfs_transfer_type".to_owned(),
+            "gtfs_zone_id".to_owned(),
             "has_physical_mode".to_owned(),
+            "has_stop".to_owned(),
+            "import_status".to_owned(),
             "instance_of".to_owned(),
+            "length".to_owned(),
+            "level".to_owned(),
+            "level_index".to_owned(),
+            "min_transfer_time".to_owned(),
+            "not_accessible".to_owned(),
+            "on_level".to_owned(),
+            "operated_by".to_owned(),
             "part_of".to_owned(),
+            "pathway_to".to_owned(),
+            "pattern_of".to_owned(),
+            "phone".to_owned(),
+            "no_longer_present_since".to_owned(),
             "physical_mode".to_owned(),
+            "platform_code".to_owned(),
             "produced_by".to_owned(),
             "producer".to_owned(),
             "railway".to_owned(),
+            "relation_count".to_owned(),
             "route".to_owned(),
+            "route_count".to_owned(),
+            "route_pattern".to_owned(),
+            "seen_in".to_owned(),
             "sha_256".to_owned(),
+            "sort_order".to_owned(),
             "source".to_owned(),
             "stop_area".to_owned(),
             "stop_boarding_area".to_owned(),
+            "stop_count".to_owned(),
             "stop_entrance".to_owned(),
             "stop_generic_node".to_owned(),
             "stop_point".to_owned(),
+            "stop_sequence".to_owned(),
             "subway".to_owned(),
+            "text_color".to_owned(),
+            "timezone".to_owned(),
             "tool_version".to_owned(),
             "tramway".to_owned(),
+            "transfer_to".to_owned(),
+            "traversal_time".to_owned(),
+            "unknown_accessibility".to_owned(),
+            "url".to_owned(),
+            "warning_count".to_owned(),
+            "wheelchair_accessibility".to_owned(),
+            "wheelchair_boarding".to_owned(),
         ],
     );
 }
 
-fn import_gtfs(docker: &utils::DockerContainerWrapper, producer_id: &str) {
-    utils::run(
-        "import-gtfs",
-        &[
-            "--producer",
-            producer_id,
-            "--input-gtfs",
-            &format!(
-                "{}/tests/fixtures/gtfs.zip",
-                std::env::var("CARGO_MANIFEST_DIR").expect("impossible to find env var")
-            ),
-            "--api",
-            &docker.api_endpoint,
-            "--sparql",
-            &docker.sparql_endpoint,
-        ],
+fn import_gtfs(docker: &utils::DockerContainerWrapper, producer_id: &str, extra_args: &[&str]) {
+    let gtfs = format!(
+        "{}/tests/fixtures/gtfs.zip",
+        std::env::var("CARGO_MANIFEST_DIR").expect("impossible to find env var")
     );
+    let mut args = vec![
+        "--producer",
+        producer_id,
+        "--input-gtfs",
+        gtfs.as_str(),
+        "--api",
+        docker.api_endpoint.as_str(),
+        "--sparql",
+        docker.sparql_endpoint.as_str(),
+    ];
+    args.extend_from_slice(extra_args);
+    utils::run("import-gtfs", &args);
 }
 
 fn create_producer(
@@ -182,8 +223,62 @@ fn simple_test() {
         create_producer("bob the bus mapper", &wikibase, &docker)
     );
 
-    // we now import a gtfs
-    import_gtfs(&docker, &producer_id);
+    // a dry run only reads the database, nothing should be created
+    utils::run(
+        "import-gtfs",
+        &[
+            "--producer",
+            &producer_id,
+            "--input-gtfs",
+            &format!(
+                "{}/tests/fixtures/gtfs.zip",
+                std::env::var("CARGO_MANIFEST_DIR").expect("impossible to find env var")
+            ),
+            "--api",
+            &docker.api_endpoint,
+            "--sparql",
+            &docker.sparql_endpoint,
+            "--dry-run",
+        ],
+    );
+    assert!(wikibase
+        .get_producer_datasources_id(&producer_id)
+        .is_empty());
+
+    // an invalid gtfs is rejected before anything is written
+    assert!(!utils::unchecked_run(
+        "import-gtfs",
+        &[
+            "--producer",
+            &producer_id,
+            "--input-gtfs",
+            &format!(
+                "{}/tests/fixtures/invalid_gtfs.zip",
+                std::env::var("CARGO_MANIFEST_DIR").expect("impossible to find env var")
+            ),
+            "--api",
+            &docker.api_endpoint,
+            "--sparql",
+            &docker.sparql_endpoint,
+        ],
+    )
+    .success());
+    assert!(wikibase
+        .get_producer_datasources_id(&producer_id)
+        .is_empty());
+
+    // we now import a gtfs, keeping a journal of the created entities
+    let journal = std::env::temp_dir().join(format!("topo_journal_{}.jsonl", producer_id));
+    let _ = std::fs::remove_file(&journal);
+    import_gtfs(
+        &docker,
+        &producer_id,
+        &[
+            "--journal",
+            journal.to_str().unwrap(),
+            "--wait-for-query-service",
+        ],
+    );
 
     // there are 1 data sources with routes imported
     let data_sources = wikibase.get_producer_datasources_id(&producer_id);
@@ -200,9 +295,14 @@ fn simple_test() {
         .ends_with("tests/fixtures/gtfs.zip"));
     assert!(!data_source.properties[&properties.sha_256][0].is_empty());
     assert!(!data_source.properties[&properties.tool_version][0].is_empty());
+    assert_eq!(
+        data_source.properties[&properties.import_status][0].value(),
+        "completed"
+    );
 
     let all_objects = wikibase.get_all_items_for_datasource(data_source_id);
-    assert_eq!(all_objects.len(), 14);
+    // 1 agency, 5 routes, 1 level, 9 stops and 9 route patterns
+    assert_eq!(all_objects.len(), 25);
 
     let find_by_gtfs_id = |gtfs_id: &str| {
         all_objects
@@ -211,6 +311,9 @@ fn simple_test() {
     };
 
     let ab = find_by_gtfs_id("AB").expect(&format!("impossible to find AB"));
+    // the creation of the route has been written in the journal
+    let journal_content = std::fs::read_to_string(&journal).expect("no journal");
+    assert!(journal_content.contains(&format!("\"id\":\"{}\"", ab.id)));
     assert_eq!(
         ab.label,
         "Bus Airport - Bullfrog (bob the bus mapper)".to_owned()
@@ -227,6 +330,43 @@ fn simple_test() {
         assert_eq!(instance_of(route), "Route");
     }
 
+    // the agency is imported and linked to the routes
+    let agency = find_by_gtfs_id("DTA").expect("impossible to find DTA");
+    assert_eq!(agency.label, "Demo Transit Authority".to_owned());
+    assert_eq!(agency.instance_of, "Agency/Operator".to_owned());
+    let raw_agency = wikibase.get_entity(&agency.id);
+    assert_eq!(
+        raw_agency.properties[&properties.url][0].value(),
+        "http://google.com"
+    );
+    assert_eq!(
+        raw_agency.properties[&properties.timezone][0].value(),
+        "America/Los_Angeles"
+    );
+    let raw_ab = wikibase.get_entity(&ab.id);
+    assert_eq!(
+        raw_ab.properties[&properties.first_seen_in][0].value(),
+        data_source_id.as_str()
+    );
+    assert_eq!(raw_ab.properties[&properties.color][0].value(), "FF0000");
+    assert_eq!(
+        raw_ab.properties[&properties.text_color][0].value(),
+        "FFFFFF"
+    );
+    assert_eq!(
+        raw_ab.properties[&properties.url][0].value(),
+        "http://example.com/routes/ab"
+    );
+    assert_eq!(
+        raw_ab.properties[&properties.gtfs_description][0].value(),
+        "Airport shuttle"
+    );
+    assert_eq!(raw_ab.properties[&properties.sort_order][0].value(), "+2");
+    assert_eq!(
+        raw_ab.properties[&properties.operated_by][0].value(),
+        agency.id
+    );
+
     for stop in &["NADAV", "NANAA", "DADAN", "EMSI", "AMV"] {
         assert_eq!(instance_of(stop), "Stop point");
     }
@@ -272,8 +412,62 @@ fn simple_test() {
         ]
     );
 
-    // we reimport the gtfs
-    import_gtfs(&docker, &producer_id);
+    // the transfer between NANAA and NADAV has been imported
+    let nanaa = find_by_gtfs_id("NANAA").expect("impossible to find NANAA");
+    let nadav = find_by_gtfs_id("NADAV").expect("impossible to find NADAV");
+    let raw_nanaa = wikibase.get_entity(&nanaa.id);
+    assert_eq!(
+        raw_nanaa.properties[&properties.transfer_to][0].value(),
+        nadav.id
+    );
+    assert_eq!(
+        wikibase.get_qualifier_values(
+            &nanaa.id,
+            &properties.transfer_to,
+            &properties.gtfs_transfer_type
+        ),
+        vec!["2".to_owned()]
+    );
+    assert_eq!(
+        wikibase.get_qualifier_values(
+            &nanaa.id,
+            &properties.transfer_to,
+            &properties.min_transfer_time
+        ),
+        vec!["180".to_owned()]
+    );
+
+    // the details of NADAV have been imported
+    let raw_nadav = wikibase.get_entity(&nadav.id);
+    assert_eq!(
+        raw_nadav.properties[&properties.gtfs_code][0].value(),
+        "1234"
+    );
+    assert_eq!(
+        raw_nadav.properties[&properties.platform_code][0].value(),
+        "A"
+    );
+    assert_eq!(
+        raw_nadav.properties[&properties.wheelchair_boarding][0].value(),
+        wikibase.items().accessible
+    );
+
+    // we reimport the same gtfs, it has already been imported so nothing is done
+    import_gtfs(&docker, &producer_id, &[]);
+    assert_eq!(
+        wikibase.get_producer_datasources_id(&producer_id),
+        data_sources
+    );
+
+    // we force the reimport of the gtfs, with a checkpoint removed once the import is completed
+    let checkpoint = std::env::temp_dir().join(format!("topo_checkpoint_{}.json", producer_id));
+    let _ = std::fs::remove_file(&checkpoint);
+    import_gtfs(
+        &docker,
+        &producer_id,
+        &["--force", "--checkpoint", checkpoint.to_str().unwrap()],
+    );
+    assert!(!checkpoint.exists());
 
     // there are now 2 datasources, because we do no merge.
     // It might change in the futur
@@ -287,6 +481,20 @@ fn simple_test() {
     let all_objects = wikibase.get_all_items_for_datasource(new_datasource.iter().next().unwrap());
     assert_eq!(all_objects.len(), 0);
 
+    // the already existing entities have been seen in both data sources
+    let raw_ab = wikibase.get_entity(&ab.id);
+    assert_eq!(
+        raw_ab.properties[&properties.first_seen_in][0].value(),
+        data_source_id.as_str()
+    );
+    assert_eq!(
+        raw_ab.properties[&properties.seen_in]
+            .iter()
+            .map(|d| d.value().to_owned())
+            .collect::<BTreeSet<_>>(),
+        new_datasources
+    );
+
     let ab = find_by_gtfs_id("AB").expect(&format!("impossible to find AB"));
     assert_eq!(
         ab.label,
@@ -341,5 +549,18 @@ fn simple_test() {
         fur_creek_res.id
     );
 
+    // the level and the pathway of BULLFROG have been imported
+    let level = find_by_gtfs_id("L0").expect("impossible to find L0");
+    assert_eq!(level.label, "Ground floor".to_owned());
+    assert_eq!(level.instance_of, "Level".to_owned());
+    assert_eq!(
+        bullfrog_details.properties[&properties.on_level][0].value(),
+        level.id
+    );
+    assert_eq!(
+        bullfrog_details.properties[&properties.pathway_to][0].value(),
+        stage_coach.id
+    );
+
     test_create_custom_property(&wikibase, &docker);
 }
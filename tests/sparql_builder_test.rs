@@ -0,0 +1,86 @@
+use pretty_assertions::assert_eq;
+use transit_topo::clients::sparql_builder::{
+    iri, lang_literal, literal, prefixed, var, wd, wdt, Expression, Term, WhereClause,
+};
+
+#[test]
+fn literals_are_escaped() {
+    assert_eq!(literal("bob").to_string(), r#""bob""#);
+    assert_eq!(
+        literal("a \"quoted\" \\ id\n").to_string(),
+        r#""a \"quoted\" \\ id\n""#
+    );
+    assert_eq!(lang_literal("bob", "en").to_string(), r#""bob"@en"#);
+    assert_eq!(
+        lang_literal("bob", "en\" . ?x ?y ?z").to_string(),
+        r#""bob"@enxyz"#
+    );
+}
+
+#[test]
+fn injection_in_a_gtfs_id_is_harmless() {
+    let clause = WhereClause::new().triple(
+        var("route"),
+        wdt("P2"),
+        literal(r#"1". ?route ?p ?o . FILTER("1"="1"#),
+    );
+    assert_eq!(
+        clause.to_string(),
+        r#"?route wdt:P2 "1\". ?route ?p ?o . FILTER(\"1\"=\"1" ."#
+    );
+}
+
+#[test]
+fn iris_and_prefixed_names_are_escaped() {
+    assert_eq!(
+        iri("https://example.com/a b>").to_string(),
+        "<https://example.com/a%20b%3E>"
+    );
+    assert_eq!(wd("Q42").to_string(), "wd:Q42");
+    assert_eq!(prefixed("rdfs", "label").to_string(), "rdfs:label");
+    assert_eq!(wd("Q42. ?x").to_string(), r"wd:Q42\.%20\?x");
+    assert_eq!(var("?a b").to_string(), "?ab");
+}
+
+#[test]
+fn patterns() {
+    let clause = WhereClause::new()
+        .values(var("type"), vec![wd("Q1"), wd("Q2")])
+        .triple(var("entity"), wdt("P3"), var("type"))
+        .optional(WhereClause::new().triple(var("entity"), wdt("P4"), var("removed")))
+        .filter(Expression::NotExists(
+            WhereClause::new().triple(var("entity"), wdt("P5"), literal("x")),
+        ))
+        .filter(Expression::NotEquals(var("type"), wd("Q3")));
+    assert_eq!(
+        clause.to_string(),
+        r#"VALUES ?type { wd:Q1 wd:Q2 } ?entity wdt:P3 ?type . OPTIONAL { ?entity wdt:P4 ?removed . } FILTER (NOT EXISTS { ?entity wdt:P5 "x" . }) FILTER (?type != wd:Q3)"#
+    );
+}
+
+#[test]
+fn parse_user_terms() {
+    assert_eq!(Term::parse(r#""bob"@en"#), Ok(lang_literal("bob", "en")));
+    assert_eq!(Term::parse(r#""bob""#), Ok(literal("bob")));
+    assert_eq!(
+        Term::parse("<https://example.com>"),
+        Ok(iri("https://example.com"))
+    );
+    assert_eq!(Term::parse("wd:Q42"), Ok(wd("Q42")));
+    assert_eq!(Term::parse("?item"), Ok(var("item")));
+    assert_eq!(Term::parse("bob"), Ok(literal("bob")));
+    assert_eq!(Term::parse(r#""foo \"bar\"""#), Ok(literal("foo \"bar\"")));
+    assert_eq!(Term::parse(r#""a\\b\tc""#), Ok(literal("a\\b\tc")));
+    assert_eq!(Term::parse("http://x"), Ok(literal("http://x")));
+    assert_eq!(Term::parse("1:2"), Ok(literal("1:2")));
+}
+
+#[test]
+fn reject_invalid_user_terms() {
+    // the text after the closing quote is not silently dropped
+    assert!(Term::parse(r#""foo"bar"#).is_err());
+    assert!(Term::parse(r#""foo"@"#).is_err());
+    assert!(Term::parse(r#""foo"@en bar"#).is_err());
+    assert!(Term::parse(r#""foo"#).is_err());
+    assert!(Term::parse(r#""foo\q""#).is_err());
+}
@@ -1,12 +1,13 @@
 //! Some utilities wikibase queries to ease tests
 use crate::utils::DockerContainerWrapper;
 use std::collections::BTreeSet;
+use transit_topo::clients::sparql_builder::{lang_literal, prefixed, var, wd, wdt, WhereClause};
 use transit_topo::clients::sparql_client::read_id_from_url;
+use transit_topo::clients::ApiClient;
 use transit_topo::topo_query::TopoQuery;
-use transit_topo::topo_writer::TopoWriter;
 
 pub struct Wikibase {
-    pub writer: TopoWriter,
+    pub api: ApiClient,
     pub query: TopoQuery,
 }
 
@@ -23,8 +24,7 @@ impl Wikibase {
         let query = TopoQuery::new(&docker.sparql_endpoint, "P1")
             .expect("impossible to create sparql client");
         Self {
-            writer: TopoWriter::new(&docker.api_endpoint, query.known_entities.clone())
-                .expect("impossible to create api client"),
+            api: ApiClient::new(&docker.api_endpoint).expect("impossible to create api client"),
             query,
         }
     }
@@ -43,7 +43,11 @@ impl Wikibase {
             .client
             .sparql(
                 &["?item"],
-                &format!(r#"?item rdfs:label "{label}"@en"#, label = label,),
+                &WhereClause::new().triple(
+                    var("item"),
+                    prefixed("rdfs", "label"),
+                    lang_literal(label, "en"),
+                ),
             )
             .expect("impossible to call sparql")
             .as_mut_slice()
@@ -58,8 +62,7 @@ impl Wikibase {
     }
 
     pub fn get_entity(&self, item: &str) -> transit_topo::entity::Entity {
-        self.writer
-            .client
+        self.api
             .get_entity(item)
             .expect("impossible to find entity")
     }
@@ -71,21 +74,15 @@ impl Wikibase {
             .client
             .sparql(
                 &["?gtfs_id ?item ?item_label ?type_label"],
-                &format!(
-                    r#"
-                        ?item wdt:{from} wd:{data_source};
-                              rdfs:label ?item_label.
-                        OPTIONAL {{
-                            ?item wdt:{gtfs_id} ?gtfs_id;
-                                  wdt:{instance_of} ?type.
-                            ?type rdfs:label ?type_label.
-                        }}
-                    "#,
-                    data_source = data_source_id,
-                    from = prop.data_source,
-                    gtfs_id = prop.gtfs_id,
-                    instance_of = prop.instance_of,
-                ),
+                &WhereClause::new()
+                    .triple(var("item"), wdt(&prop.data_source), wd(data_source_id))
+                    .triple(var("item"), prefixed("rdfs", "label"), var("item_label"))
+                    .optional(
+                        WhereClause::new()
+                            .triple(var("item"), wdt(&prop.gtfs_id), var("gtfs_id"))
+                            .triple(var("item"), wdt(&prop.instance_of), var("type"))
+                            .triple(var("type"), prefixed("rdfs", "label"), var("type_label")),
+                    ),
             )
             .expect("invalid sparql query");
 
@@ -99,6 +96,22 @@ impl Wikibase {
             .collect()
     }
 
+    /// get the values of a qualifier of the claims of an item
+    pub fn get_qualifier_values(&self, item: &str, property: &str, qualifier: &str) -> Vec<String> {
+        let r = self
+            .query
+            .client
+            .sparql(
+                &["?value"],
+                &WhereClause::new()
+                    .triple(wd(item), prefixed("p", property), var("statement"))
+                    .triple(var("statement"), prefixed("pq", qualifier), var("value")),
+            )
+            .expect("invalid sparql query");
+
+        r.into_iter().map(|res| res["value"].clone()).collect()
+    }
+
     pub fn get_producer_datasources_id(&self, producer_id: &str) -> BTreeSet<String> {
         let prop = &self.query.known_entities.properties;
         let r = self
@@ -106,10 +119,10 @@ impl Wikibase {
             .client
             .sparql(
                 &["?data_source"],
-                &format!(
-                    "?data_source wdt:{produced_by} wd:{producer}.",
-                    produced_by = prop.produced_by,
-                    producer = producer_id
+                &WhereClause::new().triple(
+                    var("data_source"),
+                    wdt(&prop.produced_by),
+                    wd(producer_id),
                 ),
             )
             .expect("invalid sparql query");
@@ -126,9 +139,10 @@ impl Wikibase {
             .client
             .sparql(
                 &["?topo_id"],
-                &format!(
-                    "?x wdt:{topo_id} ?topo_id",
-                    topo_id = self.query.known_entities.properties.topo_id_id
+                &WhereClause::new().triple(
+                    var("x"),
+                    wdt(&self.query.known_entities.properties.topo_id_id),
+                    var("topo_id"),
                 ),
             )
             .expect("invalid sparql query");